@@ -0,0 +1,187 @@
+use cargo::CargoResult;
+use serde_json;
+use std::fs;
+use std::path::Path;
+
+/// Result of a single `#[bench]` function.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub package: String,
+    pub target: String,
+    pub name: String,
+    /// Fractional on nightly, e.g. `1,234.56`
+    pub ns_per_iter: f64,
+    /// The `+/-` libtest reports along the time per iteration
+    pub variance: f64,
+}
+
+impl BenchResult {
+    /// Identifies the same benchmark across runs
+    pub fn id(&self) -> String {
+        format!("{}/{}/{}", self.package, self.target, self.name)
+    }
+}
+
+/// A benchmark present both in the baseline and in the current run.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub id: String,
+    pub baseline: f64,
+    pub current: f64,
+    /// Relative change of the time per iteration, in percent. Positive means
+    /// slower.
+    pub change: f64,
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    s.replace(',', "").parse().ok()
+}
+
+/// Parse the `test NAME ... bench: 1,234 ns/iter (+/- 56)` lines of libtest's
+/// output. The package and target are left empty.
+///
+/// A bench line whose time can't be parsed is an error rather than a
+/// benchmark silently missing from the results.
+pub fn parse_bench_output(output: &str) -> CargoResult<Vec<BenchResult>> {
+    let mut results = vec![];
+    for line in output.lines() {
+        if !line.starts_with("test ") {
+            continue;
+        }
+        let idx = match line.find(" ... bench:") {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let name = &line["test ".len()..idx];
+        let mut words = line[idx + " ... bench:".len()..].split_whitespace();
+
+        let ns_per_iter = match words.next().and_then(parse_number) {
+            Some(ns) => ns,
+            None => return Err(format_err!("couldn't parse the bench result `{}`", line)),
+        };
+        // "ns/iter", "(+/-", "56)"
+        let variance = words.nth(2)
+            .and_then(|variance| parse_number(variance.trim_end_matches(')')))
+            .unwrap_or(0.0);
+
+        results.push(BenchResult {
+            package: String::new(),
+            target: String::new(),
+            name: name.trim().to_string(),
+            ns_per_iter: ns_per_iter,
+            variance: variance,
+        });
+    }
+    Ok(results)
+}
+
+pub fn load(path: &Path) -> CargoResult<Vec<BenchResult>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format_err!("couldn't read bench results {}: {}", path.display(), err))?;
+    parse(&contents)
+}
+
+pub fn parse(json: &str) -> CargoResult<Vec<BenchResult>> {
+    Ok(serde_json::from_str(json)?)
+}
+
+pub fn save(path: &Path, results: &[BenchResult]) -> CargoResult<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(results)?)?;
+    Ok(())
+}
+
+/// Compare the benchmarks of `current` with the ones of the same name in
+/// `baseline`. Benchmarks which are new or gone are ignored.
+pub fn compare(baseline: &[BenchResult], current: &[BenchResult]) -> Vec<Comparison> {
+    current.iter()
+        .filter_map(|result| {
+            let id = result.id();
+            baseline.iter().find(|previous| previous.id() == id).map(|previous| {
+                let change = if previous.ns_per_iter == 0.0 {
+                    0.0
+                } else {
+                    (result.ns_per_iter - previous.ns_per_iter) * 100.0 / previous.ns_per_iter
+                };
+                Comparison {
+                    id: id,
+                    baseline: previous.ns_per_iter,
+                    current: result.ns_per_iter,
+                    change: change,
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, ns_per_iter: f64) -> BenchResult {
+        BenchResult {
+            package: "foo".to_string(),
+            target: "bar".to_string(),
+            name: name.to_string(),
+            ns_per_iter: ns_per_iter,
+            variance: 0.0,
+        }
+    }
+
+    #[test]
+    fn parse_integer_times() {
+        let output = "\
+running 2 tests
+test tests::fast ... bench:          12 ns/iter (+/- 3)
+test tests::slow ... bench:   1,234,567 ns/iter (+/- 8,910)
+
+test result: ok. 0 passed; 0 failed; 0 ignored; 2 measured; 0 filtered out
+";
+        let results = parse_bench_output(output).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].name, "tests::fast");
+        assert_eq!(results[0].ns_per_iter, 12.0);
+        assert_eq!(results[0].variance, 3.0);
+        assert_eq!(results[1].name, "tests::slow");
+        assert_eq!(results[1].ns_per_iter, 1234567.0);
+        assert_eq!(results[1].variance, 8910.0);
+    }
+
+    #[test]
+    fn parse_fractional_times() {
+        let output = "test tests::nightly ... bench:       1,234.56 ns/iter (+/- 7.89)\n";
+        let results = parse_bench_output(output).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].ns_per_iter, 1234.56);
+        assert_eq!(results[0].variance, 7.89);
+    }
+
+    #[test]
+    fn parse_unknown_times() {
+        assert!(parse_bench_output("test tests::odd ... bench: fast ns/iter (+/- 1)\n").is_err());
+        // Tests run along the benchmarks aren't benchmarks
+        assert!(parse_bench_output("test tests::unit ... ok\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn compare_same_benchmarks() {
+        let baseline = vec![result("a", 100.0), result("b", 200.0), result("gone", 1.0)];
+        let current = vec![result("a", 150.0), result("b", 100.0), result("new", 1.0)];
+        let comparisons = compare(&baseline, &current);
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].id, "foo/bar/a");
+        assert_eq!(comparisons[0].baseline, 100.0);
+        assert_eq!(comparisons[0].current, 150.0);
+        assert_eq!(comparisons[0].change, 50.0);
+        assert_eq!(comparisons[1].id, "foo/bar/b");
+        assert_eq!(comparisons[1].change, -50.0);
+    }
+
+    #[test]
+    fn compare_zero_baseline() {
+        let comparisons = compare(&[result("a", 0.0)], &[result("a", 10.0)]);
+        assert_eq!(comparisons[0].change, 0.0);
+    }
+}
@@ -0,0 +1,5 @@
+extern crate cargo_travis;
+
+fn main() {
+    cargo_travis::cli::alias_main("coverage-upload");
+}
@@ -0,0 +1,416 @@
+use cargo::core::Workspace;
+
+/// Packages kcov needs to be built, on Ubuntu
+pub const KCOV_PACKAGES: &[&str] = &["libcurl4-openssl-dev", "libelf-dev", "libdw-dev", "binutils-dev", "cmake"];
+
+/// Features conventionally only enabled on nightly toolchains
+const NIGHTLY_FEATURES: &[&str] = &["unstable", "nightly"];
+
+/// What the generated CI configuration depends on.
+#[derive(Clone, Debug)]
+pub struct Project {
+    /// Whether the workspace has more than one member, and every command
+    /// needs `--all`
+    pub workspace: bool,
+    /// Whether any member has a library, i.e. documentation worth publishing.
+    /// The documentation of binaries isn't.
+    pub has_lib: bool,
+    /// A feature to only enable on nightly, like travis-cargo's `unstable`
+    pub nightly_feature: Option<String>,
+}
+
+impl Project {
+    pub fn inspect(ws: &Workspace) -> Project {
+        let mut project = Project {
+            workspace: ws.members().count() > 1,
+            has_lib: false,
+            nightly_feature: None,
+        };
+        for package in ws.members() {
+            project.has_lib |= package.targets().iter().any(|target| target.is_lib());
+            if project.nightly_feature.is_none() {
+                project.nightly_feature = package.summary().features().keys()
+                    .find(|feature| NIGHTLY_FEATURES.contains(&&***feature))
+                    .map(|feature| feature.to_string());
+            }
+        }
+        project
+    }
+
+    /// Flags selecting the packages to build
+    fn packages(&self) -> &'static str {
+        if self.workspace { " --all" } else { "" }
+    }
+
+    /// `cargo build` and friends, as a single shell command. `channel` is the
+    /// shell expression of the channel the build is on.
+    fn build_commands(&self, channel: &str) -> String {
+        let mut flags = self.packages().to_string();
+        if let Some(ref feature) = self.nightly_feature {
+            flags.push_str(&format!(" $([ \"{}\" = nightly ] && echo --features {})", channel, feature));
+        }
+        let mut commands = vec![format!("cargo build{}", flags), format!("cargo test{}", flags)];
+        if self.has_lib {
+            commands.push(format!("cargo doc{}", self.packages()));
+        }
+        commands.join(" &&\n      ")
+    }
+}
+
+/// Render a `.travis.yml` testing `project` on all channels, and uploading its
+/// coverage to coveralls.io and its documentation to GitHub pages from stable.
+pub fn travis_yml(project: &Project) -> String {
+    let mut yml = String::new();
+    yml.push_str("sudo: required\nlanguage: rust\n\n");
+    yml.push_str("# Cache cargo symbols for faster build\ncache: cargo\n\n");
+    yml.push_str("# Dependencies of kcov, used by coverage\naddons:\n  apt:\n    packages:\n");
+    for package in KCOV_PACKAGES {
+        yml.push_str(&format!("      - {}\n", package));
+    }
+    yml.push_str("    sources:\n      - kalakris-cmake\n\n");
+    yml.push_str("rust:\n  - nightly\n  - beta\n  - stable\n\n");
+    yml.push_str("before_script:\n");
+    yml.push_str("  - export PATH=$HOME/.cargo/bin:$PATH\n");
+    yml.push_str("  - cargo install cargo-travis || echo \"cargo-travis already installed\"\n\n");
+    yml.push_str(&format!("script:\n  - |\n      {}\n\n", project.build_commands("$TRAVIS_RUST_VERSION")));
+    yml.push_str("after_success:\n");
+    yml.push_str("# measure code coverage and upload to coveralls.io\n");
+    yml.push_str(&format!("  - cargo coveralls --only stable{}\n", project.packages()));
+    if project.has_lib {
+        yml.push_str("# upload documentation to github.io (gh-pages branch)\n");
+        yml.push_str("  - cargo doc-upload --only stable\n");
+    }
+    yml
+}
+
+/// Render a GitHub Actions workflow testing `project` on all channels, and
+/// uploading its coverage report and documentation to GitHub pages from stable.
+///
+/// The commands expect travis' environment, so the workflow sets the variables
+/// they need.
+pub fn github_workflow(project: &Project) -> String {
+    let mut yml = String::new();
+    yml.push_str("name: CI\n\non: [push, pull_request]\n\n");
+    yml.push_str("jobs:\n  test:\n    runs-on: ubuntu-latest\n");
+    yml.push_str("    strategy:\n      matrix:\n        rust: [nightly, beta, stable]\n");
+    yml.push_str("    env:\n");
+    yml.push_str("      TRAVIS_PULL_REQUEST: ${{ github.event_name == 'pull_request' }}\n");
+    yml.push_str("      TRAVIS_REPO_SLUG: ${{ github.repository }}\n");
+    yml.push_str("      TRAVIS_COMMIT: ${{ github.sha }}\n");
+    yml.push_str("      # A personal access token allowed to push to the repository\n");
+    yml.push_str("      GH_TOKEN: ${{ secrets.GH_TOKEN }}\n");
+    yml.push_str("    steps:\n");
+    yml.push_str("      - uses: actions/checkout@v4\n");
+    yml.push_str("      - run: rustup default ${{ matrix.rust }}\n");
+    yml.push_str("      # Dependencies of kcov, used by coverage\n");
+    yml.push_str(&format!("      - run: sudo apt-get update && sudo apt-get install -y {}\n", KCOV_PACKAGES.join(" ")));
+    yml.push_str("      - run: cargo install cargo-travis\n");
+    yml.push_str(&format!("      - run: |\n          {}\n", project.build_commands("${{ matrix.rust }}").replace("\n      ", "\n          ")));
+    yml.push_str(&format!("      - run: cargo coverage --only stable{}\n", project.packages()));
+    yml.push_str("      - run: |\n");
+    yml.push_str("          export TRAVIS_BRANCH=${GITHUB_REF#refs/heads/}\n");
+    if project.has_lib {
+        yml.push_str("          cargo doc-upload --only stable\n");
+    }
+    yml.push_str("          cargo coverage-upload --only stable\n");
+    yml
+}
+
+/// The phases of a travis build, in the order they run
+const PHASES: &[&str] = &["before_install", "install", "before_script", "script",
+                          "after_success", "after_failure", "before_deploy", "deploy",
+                          "after_deploy", "after_script"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that may be fine, e.g. depending on the repository settings
+    Hint,
+    /// Something that breaks cargo-travis
+    Error,
+}
+
+/// A problem found in a `.travis.yml`.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    /// The line the problem is at, starting from 1, if it's about a line
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// A non-empty line of a YAML file.
+struct YamlLine<'a> {
+    number: usize,
+    /// The keys of the mappings this line is in, from the top level
+    path: Vec<&'a str>,
+    /// The key on this line, if any
+    key: Option<&'a str>,
+    /// The value on this line, i.e. what follows the key or list item marker
+    value: &'a str,
+    is_item: bool,
+}
+
+fn strip_comment(line: &str) -> &str {
+    if line.trim_start().starts_with('#') {
+        return "";
+    }
+    match line.find(" #") {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
+fn split_key(content: &str) -> (Option<&str>, &str) {
+    if let Some(idx) = content.find(':') {
+        let key = &content[..idx];
+        let rest = &content[idx + 1..];
+        let is_key = !key.is_empty()
+            && key.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            && (rest.is_empty() || rest.starts_with(' '));
+        if is_key {
+            return (Some(key), rest.trim());
+        }
+    }
+    (None, content)
+}
+
+/// Split `yml` in lines, tracking which mapping each one is in.
+///
+/// This only understands the block style travis configurations are written
+/// in, and treats the lines of multi-line strings as nested values, which is
+/// all the checks below need.
+fn yaml_lines<'a>(yml: &'a str) -> Vec<YamlLine<'a>> {
+    let mut lines = vec![];
+    // (indentation of the key, key)
+    let mut stack: Vec<(usize, &str)> = vec![];
+
+    for (idx, line) in yml.lines().enumerate() {
+        let line = strip_comment(line).trim_end();
+        let content = line.trim_start();
+        if content.is_empty() || content == "---" {
+            continue;
+        }
+        let indent = line.len() - content.len();
+        let is_item = content == "-" || content.starts_with("- ");
+
+        // List items may be at the same indentation as their parent key
+        while stack.last().map_or(false, |&(key_indent, _)| indent < key_indent || (indent == key_indent && !is_item)) {
+            stack.pop();
+        }
+
+        let content = if is_item { content[1..].trim_start() } else { content };
+        let (key, value) = split_key(content);
+        lines.push(YamlLine {
+            number: idx + 1,
+            path: stack.iter().map(|&(_, key)| key).collect(),
+            key: key,
+            value: value,
+            is_item: is_item,
+        });
+
+        if let Some(key) = key {
+            if value.is_empty() || value == "|" || value == ">" {
+                stack.push((if is_item { indent + 2 } else { indent }, key));
+            }
+        }
+    }
+    lines
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim().trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// The items of a block (`- item`) or flow (`[item, item]`) list
+fn list_items<'a>(lines: &[YamlLine<'a>], path: &[&str]) -> Vec<(usize, &'a str)> {
+    let mut items = vec![];
+    for line in lines {
+        if line.path == path && line.is_item {
+            items.push((line.number, unquote(line.value)));
+        } else if line.path[..] == path[..path.len() - 1] && line.key == path.last().cloned()
+            && line.value.starts_with('[') && line.value.ends_with(']') {
+            let inner = &line.value[1..line.value.len() - 1];
+            items.extend(inner.split(',').map(unquote).filter(|item| !item.is_empty()).map(|item| (line.number, item)));
+        }
+    }
+    items
+}
+
+/// Whether `line` runs the cargo-travis command `name`, either through its
+/// alias or through `cargo travis`
+fn runs(line: &str, name: &str) -> bool {
+    line.split(|c: char| c.is_whitespace() || c == ';' || c == '&' || c == '|')
+        .any(|word| word == name || word == format!("cargo-{}", name))
+}
+
+/// Whether `line` builds the documentation, including with `doc-upload --build`
+fn builds_doc(line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    words.windows(2).any(|words| words[0] == "cargo" && (words[1] == "doc" || words[1] == "rustdoc"))
+        || (runs(line, "doc-upload") && words.contains(&"--build"))
+}
+
+/// Check that the travis configuration `yml` has what the cargo-travis
+/// commands it runs need.
+pub fn check_travis_yml(yml: &str) -> Vec<Problem> {
+    let lines = yaml_lines(yml);
+    let mut problems = vec![];
+
+    let top_level = |key: &str| lines.iter().find(|line| line.path.is_empty() && line.key == Some(key));
+    // The lines of the build commands, in the order they run
+    let mut commands: Vec<&YamlLine> = lines.iter()
+        .filter(|line| line.path.first().map_or(false, |phase| PHASES.contains(phase)))
+        .collect();
+    commands.sort_by_key(|line| (PHASES.iter().position(|phase| *phase == line.path[0]), line.number));
+
+    let uses_kcov = commands.iter().any(|line| runs(line.value, "coverage") || runs(line.value, "coveralls"));
+    if uses_kcov {
+        match top_level("sudo") {
+            Some(line) if line.value == "required" || line.value == "true" => {}
+            Some(line) => problems.push(Problem {
+                severity: Severity::Error,
+                line: Some(line.number),
+                message: format!("`sudo: {}` doesn't allow kcov to run, use `sudo: required`", line.value),
+            }),
+            None => problems.push(Problem {
+                severity: Severity::Error,
+                line: None,
+                message: "`sudo: required` is missing, kcov can't run without it".to_string(),
+            }),
+        }
+
+        let packages = list_items(&lines, &["addons", "apt", "packages"]);
+        let line = lines.iter()
+            .find(|line| line.key == Some("packages") && line.path == ["addons", "apt"])
+            .or_else(|| top_level("addons"))
+            .map(|line| line.number);
+        for package in KCOV_PACKAGES {
+            if !packages.iter().any(|&(_, name)| name == *package) {
+                problems.push(Problem {
+                    severity: Severity::Error,
+                    line: line,
+                    message: format!("the apt package `{}` is missing, it's needed to build kcov", package),
+                });
+            }
+        }
+    }
+
+    let uploads: Vec<&&YamlLine> = commands.iter()
+        .filter(|line| runs(line.value, "doc-upload") || runs(line.value, "coverage-upload"))
+        .collect();
+    if let Some(upload) = commands.iter().find(|line| runs(line.value, "doc-upload")) {
+        let doc_position = commands.iter().position(|line| builds_doc(line.value));
+        let upload_position = commands.iter().position(|line| line.number == upload.number);
+        if doc_position.is_none() || doc_position > upload_position {
+            problems.push(Problem {
+                severity: Severity::Error,
+                line: Some(upload.number),
+                message: "doc-upload runs before `cargo doc`, so there's no documentation to upload".to_string(),
+            });
+        }
+    }
+
+    if let Some(upload) = uploads.first() {
+        let env: Vec<&YamlLine> = lines.iter().filter(|line| line.path.first() == Some(&"env")).collect();
+        if let Some(line) = env.iter().find(|line| line.value.contains("GH_TOKEN=")) {
+            problems.push(Problem {
+                severity: Severity::Error,
+                line: Some(line.number),
+                message: "GH_TOKEN is in clear text, anyone can push to the repository with it. \
+                          Encrypt it with `travis encrypt` or define it in the repository settings".to_string(),
+            });
+        } else if !env.iter().any(|line| line.key == Some("secure")) && !yml.contains("--token") {
+            problems.push(Problem {
+                severity: Severity::Hint,
+                line: Some(upload.number),
+                message: "GH_TOKEN isn't set here, so make sure it's defined in the repository settings, \
+                          or the upload falls back to SSH".to_string(),
+            });
+        }
+    }
+
+    problems.sort_by_key(|problem| problem.line);
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `.travis.yml` of the README's example
+    fn readme_example() -> &'static str {
+        let readme = include_str!("../README.md");
+        let start = readme.find("```yaml\n").unwrap() + "```yaml\n".len();
+        let end = start + readme[start..].find("```").unwrap();
+        &readme[start..end]
+    }
+
+    fn errors(yml: &str) -> Vec<String> {
+        check_travis_yml(yml).into_iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .map(|problem| problem.message)
+            .collect()
+    }
+
+    #[test]
+    fn readme_example_is_fine() {
+        assert_eq!(errors(readme_example()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn generated_config_is_fine() {
+        let project = Project { workspace: true, has_lib: true, nightly_feature: Some("unstable".to_string()) };
+        assert_eq!(errors(&travis_yml(&project)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn missing_sudo() {
+        let yml = readme_example().replace("sudo: required\n", "");
+        assert_eq!(errors(&yml), vec!["`sudo: required` is missing, kcov can't run without it"]);
+
+        let yml = readme_example().replace("sudo: required", "sudo: false");
+        let problems = check_travis_yml(&yml);
+        assert_eq!(problems[0].line, Some(1));
+        assert_eq!(problems[0].message, "`sudo: false` doesn't allow kcov to run, use `sudo: required`");
+    }
+
+    #[test]
+    fn flow_list() {
+        let yml = "addons:\n  apt:\n    packages: [libelf-dev, 'cmake']\n    sources: [kalakris-cmake]\n";
+        let lines = yaml_lines(yml);
+        assert_eq!(list_items(&lines, &["addons", "apt", "packages"]), vec![(3, "libelf-dev"), (3, "cmake")]);
+        assert_eq!(list_items(&lines, &["addons", "apt", "sources"]), vec![(4, "kalakris-cmake")]);
+
+        let packages = format!("packages: [{}]", KCOV_PACKAGES.join(", "));
+        let yml = readme_example().replace("packages:", &packages).replace("      - libcurl4-openssl-dev\n", "")
+            .replace("      - libelf-dev\n", "").replace("      - libdw-dev\n", "")
+            .replace("      - binutils-dev\n", "").replace("      - cmake # also required for cargo-update\n", "");
+        assert_eq!(errors(&yml), Vec::<String>::new());
+    }
+
+    #[test]
+    fn items_at_the_indentation_of_their_key() {
+        let yml = "addons:\n  apt:\n    packages:\n    - cmake\n    - libdw-dev\n    sources:\n    - kalakris-cmake\nrust:\n- stable\n";
+        let lines = yaml_lines(yml);
+        assert_eq!(list_items(&lines, &["addons", "apt", "packages"]), vec![(4, "cmake"), (5, "libdw-dev")]);
+        assert_eq!(list_items(&lines, &["addons", "apt", "sources"]), vec![(7, "kalakris-cmake")]);
+        assert_eq!(list_items(&lines, &["rust"]), vec![(9, "stable")]);
+
+        let yml = "script:\n- cargo doc\nafter_success:\n- cargo doc-upload\n";
+        assert_eq!(errors(yml), Vec::<String>::new());
+    }
+
+    #[test]
+    fn upload_before_doc() {
+        let yml = "script:\n  - cargo doc-upload\n  - cargo doc\n";
+        let problems = check_travis_yml(yml);
+        assert_eq!(problems[0].severity, Severity::Error);
+        assert_eq!(problems[0].line, Some(2));
+        assert_eq!(problems[0].message, "doc-upload runs before `cargo doc`, so there's no documentation to upload");
+
+        // Phases run in their order, whatever the order they're written in
+        let yml = "after_success:\n  - cargo doc-upload\nscript:\n  - cargo doc\n";
+        assert_eq!(errors(yml), Vec::<String>::new());
+        assert_eq!(errors("after_success:\n  - cargo doc-upload --build\n"), Vec::<String>::new());
+    }
+}
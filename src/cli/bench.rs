@@ -0,0 +1,99 @@
+use cargo::core::compiler::CompileMode;
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use failure::err_msg;
+use std::path::Path;
+use super::{TestOptions, TEST_OPTIONS};
+use {bench, read_from_branch, run_benches, BenchOptions};
+
+pub const USAGE: &'static str = "
+Run all benchmarks like `cargo bench`, save their results as JSON and compare
+them with the results of a previous run.
+
+Usage:
+    cargo travis bench [options] [--] [<args>...]
+    cargo travis-bench [options] [--] [<args>...]
+
+Bench Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    -o PATH, --output PATH       Path to the file to save the results into
+                                 [default: target/bench.json]
+    --baseline PATH              Results of a previous run to compare against
+    --baseline-branch BRANCH     Read the --baseline file from this branch of the
+                                 `origin` remote (e.g. the deploy branch) instead
+    --threshold PERCENT          Slowdown from which a benchmark is considered to
+                                 have regressed [default: 10]
+    --fail-on-regression         Fail instead of warning when a benchmark regressed
+";
+
+pub fn usage() -> String {
+    format!("{}{}", USAGE, TEST_OPTIONS)
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_output: String,
+    flag_baseline: Option<String>,
+    flag_baseline_branch: Option<String>,
+    flag_threshold: f64,
+    flag_fail_on_regression: bool,
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let test_options: TestOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+
+    try!(test_options.configure(config));
+    let ws = try!(test_options.workspace(config));
+
+    // Read the baseline before the results are saved, as it may be the very
+    // file they are saved to, e.g. with a cached target directory
+    let baseline = match (options.flag_baseline, options.flag_baseline_branch) {
+        (Some(path), Some(branch)) => Some(try!(bench::parse(&try!(read_from_branch(&branch, &path))))),
+        (Some(path), None) => Some(try!(bench::load(Path::new(&path)))),
+        (None, Some(_)) => return Err(CliError::new(err_msg("--baseline-branch requires --baseline"), 1)),
+        (None, None) => None,
+    };
+
+    let mut compile_opts = try!(test_options.compile_options(config, CompileMode::Bench));
+    compile_opts.build_config.release = true;
+
+    let ops = BenchOptions {
+        no_fail_fast: test_options.flag_no_fail_fast,
+        compile_opts: compile_opts,
+    };
+
+    let (results, err) = try!(run_benches(&ws, &ops, &test_options.arg_args));
+
+    if let Some(err) = err {
+        return Err(super::test_error(err, "bench"));
+    }
+
+    let output = Path::new(&options.flag_output);
+    try!(ws.config().shell().status("Writing", output.display().to_string()));
+    try!(bench::save(output, &results));
+
+    if let Some(baseline) = baseline {
+        let mut regressions = 0;
+        for comparison in bench::compare(&baseline, &results) {
+            let regressed = comparison.change > options.flag_threshold;
+            if regressed {
+                regressions += 1;
+            }
+            println!("{:>10} {} ({} ns/iter -> {} ns/iter, {:+.2}%)",
+                     if regressed { "REGRESSED" } else { "ok" },
+                     comparison.id, comparison.baseline, comparison.current, comparison.change);
+        }
+
+        if regressions > 0 {
+            let message = format!("{} benchmark(s) regressed by more than {}%", regressions, options.flag_threshold);
+            if options.flag_fail_on_regression {
+                return Err(CliError::new(err_msg(message), 1));
+            }
+            try!(ws.config().shell().warn(message));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use std::env;
+use std::fs;
+use ci::{self, Severity};
+
+pub const USAGE: &'static str = "
+Check that a .travis.yml has what the cargo-travis commands it runs need: sudo,
+the apt packages kcov is built with, documentation built before doc-upload and
+a GitHub token for the uploads.
+
+Usage:
+    cargo travis check-config [options]
+
+Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    --config PATH                Path to the travis configuration [default: .travis.yml]
+";
+
+pub fn usage() -> String {
+    USAGE.to_string()
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_config: String,
+}
+
+pub fn execute(args: ArgvMap, _: &mut Config) -> CliResult {
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+
+    let yml = try!(fs::read_to_string(&options.flag_config)
+        .map_err(|err| CliError::new(format_err!("couldn't read {}: {}", options.flag_config, err), 1)));
+
+    let mut problems = ci::check_travis_yml(&yml);
+    // On travis, we can also tell whether the token made it to the build
+    if env::var("TRAVIS").ok() == Some("true".to_string()) && env::var_os("GH_TOKEN").is_none() {
+        problems.push(ci::Problem {
+            severity: Severity::Hint,
+            line: None,
+            message: "GH_TOKEN isn't set in this build, uploads will use SSH".to_string(),
+        });
+    }
+
+    for problem in &problems {
+        let severity = match problem.severity {
+            Severity::Error => "error",
+            Severity::Hint => "hint",
+        };
+        match problem.line {
+            Some(line) => println!("{}:{}: {}: {}", options.flag_config, line, severity, problem.message),
+            None => println!("{}: {}: {}", options.flag_config, severity, problem.message),
+        }
+    }
+
+    let errors = problems.iter().filter(|problem| problem.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(CliError::new(format_err!("{} found {} problem(s)", options.flag_config, errors), 1));
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use cargo::core::compiler::CompileMode;
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use serde_json;
+use std::path::Path;
+use super::{KcovOptions, TestOptions, KCOV_OPTIONS, TEST_OPTIONS};
+use {build_kcov, run_coverage, write_coverage_badge, write_coverage_summary, CoverageOptions};
+
+pub const USAGE: &'static str = "
+Record coverage of `cargo test`, this runs all binaries that `cargo test` runs
+but not doc tests. The results of all tests are merged into a single directory
+
+Usage:
+    cargo travis coverage [options] [--] [<args>...]
+    cargo coverage [options] [--] [<args>...]
+
+Coverage Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    -m PATH, --merge-into PATH   Path to the directory to put the final merged
+                                 kcov result into [default: target/kcov]
+    --summary-markdown PATH      Write a Markdown summary of the merged coverage to PATH
+    --summary-baseline PATH      Coverage report (merge directory or coverage.json) to
+                                 compare against in the Markdown summary
+    --badge DIR                  Write a coverage badge (badge.svg and shields.io's badge.json)
+                                 into DIR
+";
+
+pub fn usage() -> String {
+    format!("{}{}{}", USAGE, KCOV_OPTIONS, TEST_OPTIONS)
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_merge_into: String,
+    flag_summary_markdown: Option<String>,
+    flag_summary_baseline: Option<String>,
+    flag_badge: Option<String>,
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let test_options: TestOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let kcov_options: KcovOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+
+    try!(kcov_options.check());
+
+    let kcov_path = build_kcov(&kcov_options.flag_kcov_build_location);
+    // TODO: build_kcov() - Might be a good idea to consider linking kcov as a
+    // lib instead ?
+    try!(test_options.configure(config));
+    let ws = try!(test_options.workspace(config));
+
+    // TODO: Force compilation target == host, kcov
+    let ops = CoverageOptions {
+        merge_dir: Path::new(&options.flag_merge_into),
+        merge_args: vec![],
+        no_fail_fast: test_options.flag_no_fail_fast,
+        kcov_path: &kcov_path,
+        exclude_pattern: kcov_options.flag_exclude_pattern,
+        junit_path: kcov_options.flag_junit.as_ref().map(Path::new),
+        compile_opts: try!(test_options.compile_options(config, CompileMode::Test)),
+    };
+
+    let run = try!(run_coverage(&ws, &ops, &test_options.arg_args));
+    if kcov_options.flag_message_format == "json" {
+        println!("{}", try!(serde_json::to_string(&run).map_err(|e| CliError::new(e.into(), 101))));
+    }
+    let err = run.error;
+
+    // Without --no-fail-fast, a failure stops everything before the merge
+    if err.is_none() || test_options.flag_no_fail_fast {
+        if let Some(ref path) = options.flag_summary_markdown {
+            try!(write_coverage_summary(&ws, ops.merge_dir,
+                                        options.flag_summary_baseline.as_ref().map(Path::new),
+                                        Path::new(path)));
+        }
+        if let Some(ref dir) = options.flag_badge {
+            try!(write_coverage_badge(&ws, ops.merge_dir, Path::new(dir)));
+        }
+    }
+
+    match err {
+        None => Ok(()),
+        Some(err) => Err(super::test_error(err, "test")),
+    }
+}
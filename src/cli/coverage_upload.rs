@@ -0,0 +1,55 @@
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use failure::err_msg;
+use std::path::Path;
+use super::{DeployOptions, DEPLOY_OPTIONS};
+use coverage_upload;
+
+// Note about --path: we don't use the proper default syntax because the default
+// value depends on an env variable.
+pub const USAGE: &'static str = "
+Upload the merged coverage report of `cargo coverage` to GitHub pages.
+
+Usage:
+    cargo travis coverage-upload [options]
+    cargo coverage-upload [options]
+
+Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    --path PATH                  Upload the report to the specified remote path (defaults to $TRAVIS_BRANCH/coverage/)
+    -m PATH, --merge-into PATH   Path to the directory the merged kcov result was put into
+                                 by `cargo coverage` [default: target/kcov]
+";
+
+pub fn usage() -> String {
+    format!("{}{}", USAGE, DEPLOY_OPTIONS)
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_path: Option<String>,
+    flag_merge_into: String,
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let deploy_options: DeployOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let max_history = try!(deploy_options.max_history());
+
+    let branch = match deploy_options.branch() {
+        Some(branch) => branch,
+        None => return Ok(()),
+    };
+
+    let path = options.flag_path.unwrap_or_else(|| format!("{}/coverage", branch));
+    let origin = deploy_options.origin();
+
+    let message = deploy_options.message("Automatic Travis coverage report", &branch, config);
+
+    match coverage_upload(&message, &deploy_options.author(), &origin, deploy_options.deploy_branch(), &branch, &path,
+                          Path::new(&options.flag_merge_into), max_history, deploy_options.flag_dry_run) {
+        Ok(..) => Ok(()),
+        Err((string, err)) => Err(CliError::new(err_msg(origin.redact(&string)), err)),
+    }
+}
@@ -0,0 +1,87 @@
+use cargo::core::compiler::CompileMode;
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use serde_json;
+use std::env;
+use std::path::Path;
+use super::{KcovOptions, TestOptions, KCOV_OPTIONS, TEST_OPTIONS};
+use {build_kcov, run_coverage, write_coveralls_payload, CoverageOptions};
+
+pub const USAGE: &'static str = "
+Record coverage of `cargo test`, this runs all binaries that `cargo test` runs
+but not doc tests. The results of all tests are sent to coveralls.io
+
+Usage:
+    cargo travis coveralls [options] [--] [<args>...]
+    cargo coveralls [options] [--] [<args>...]
+
+Coveralls Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    --dry-run                    Write the coveralls.io job payload to the --payload file
+                                 instead of sending it
+    --payload PATH               Where --dry-run writes the job payload
+                                 [default: target/kcov/coveralls.json]
+";
+
+pub fn usage() -> String {
+    format!("{}{}{}", USAGE, KCOV_OPTIONS, TEST_OPTIONS)
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_dry_run: bool,
+    flag_payload: String,
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let test_options: TestOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let kcov_options: KcovOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+
+    try!(kcov_options.check());
+
+    let kcov_path = build_kcov(&kcov_options.flag_kcov_build_location);
+    try!(test_options.configure(config));
+    let ws = try!(test_options.workspace(config));
+
+    // kcov sends the results itself when given the job id, so it only gets it
+    // when we actually want to send them
+    let job_id = if options.flag_dry_run {
+        env::var_os("TRAVIS_JOB_ID").unwrap_or_default()
+    } else {
+        env::var_os("TRAVIS_JOB_ID")
+            .expect("Environment variable TRAVIS_JOB_ID not found. This should be run from Travis")
+    };
+    let merge_args = if options.flag_dry_run {
+        vec![]
+    } else {
+        vec!["--coveralls-id".into(), job_id.clone()]
+    };
+
+    let ops = CoverageOptions {
+        merge_dir: Path::new("target/kcov"),
+        merge_args: merge_args,
+        no_fail_fast: test_options.flag_no_fail_fast,
+        exclude_pattern: kcov_options.flag_exclude_pattern,
+        junit_path: kcov_options.flag_junit.as_ref().map(Path::new),
+        kcov_path: &kcov_path,
+        compile_opts: try!(test_options.compile_options(config, CompileMode::Test)),
+    };
+
+    let run = try!(run_coverage(&ws, &ops, &test_options.arg_args));
+    if kcov_options.flag_message_format == "json" {
+        println!("{}", try!(serde_json::to_string(&run).map_err(|e| CliError::new(e.into(), 101))));
+    }
+    let err = run.error;
+
+    if options.flag_dry_run && (err.is_none() || test_options.flag_no_fail_fast) {
+        try!(write_coveralls_payload(&ws, ops.merge_dir, &job_id.to_string_lossy(),
+                                     Path::new(&options.flag_payload)));
+    }
+
+    match err {
+        None => Ok(()),
+        Some(err) => Err(super::test_error(err, "test")),
+    }
+}
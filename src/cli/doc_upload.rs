@@ -0,0 +1,199 @@
+use cargo;
+use cargo::core::compiler::{BuildConfig, CompileMode};
+use cargo::core::Workspace;
+use cargo::ops::{self, CompileOptions, DocOptions, Packages};
+use cargo::util::{CliError, CliResult, Config};
+use cargo::CargoResult;
+use docopt::ArgvMap;
+use failure::err_msg;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use super::{features, DeployOptions, DEPLOY_OPTIONS};
+use {doc_upload, links, main_package, PruneOptions};
+
+// Note about --path: we don't use the proper default syntax because the default
+// value depends on an env variable.
+pub const USAGE: &'static str = "
+Upload built rustdoc documentation to GitHub pages.
+
+Usage:
+    cargo travis doc-upload [options]
+    cargo doc-upload [options]
+
+Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    --path PATH                  Upload the documentation to the specified remote path (defaults to $TRAVIS_BRANCH/,
+                                 or to vVERSION/ for the build of a tag, TAG/ if the version
+                                 of a virtual manifest isn't known)
+    --clobber-index              Delete `index.html` from repo
+    --target TRIPLE              Build and fetch the documentation for the target triple
+    --prune                      Remove the documentation of the branches and tags deleted
+                                 from the remote
+    --keep-tags                  With --prune, keep the documentation of deleted tags
+    --max-age DAYS               With --prune, also remove the documentation not updated
+                                 for DAYS days
+    --protect PATH ...           With --prune, never remove the documentation in PATH
+    --prune-unknown              With --prune, also remove the documentation uploaded without
+                                 recording its branch, if there's no branch named like its path
+    --check-links                Report the broken relative links and anchors of the
+                                 documentation before uploading it
+    --deny-broken-links          With --check-links, don't upload documentation with broken links
+";
+
+pub const BUILD_OPTIONS: &'static str = "
+Build Options:
+    --build                      Build the documentation with `cargo doc` before uploading it
+    -p SPEC, --package SPEC ...  Package to document
+    --all                        Document all packages in the workspace
+    --exclude SPEC ...           Exclude packages from the documentation
+    --features FEATURES          Space-separated list of features to also build
+    --nightly-feature FEATURE    Also build FEATURE when the toolchain is nightly
+    --all-features               Build all available features
+    --no-default-features        Do not build the `default` feature
+    --no-deps                    Don't build the documentation of the dependencies
+    --document-private-items     Document private items
+    -j N, --jobs N               Number of parallel jobs, defaults to # of CPUs
+    --manifest-path PATH         Path to the manifest to document
+";
+
+pub fn usage() -> String {
+    format!("{}{}{}", USAGE, BUILD_OPTIONS, DEPLOY_OPTIONS)
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_path: Option<String>,
+    flag_clobber_index: bool,
+    flag_target: Option<String>,
+    flag_prune: bool,
+    flag_keep_tags: bool,
+    flag_max_age: Option<u64>,
+    flag_protect: Vec<String>,
+    flag_prune_unknown: bool,
+    flag_check_links: bool,
+    flag_deny_broken_links: bool,
+    flag_build: bool,
+    flag_package: Vec<String>,
+    flag_all: bool,
+    flag_exclude: Vec<String>,
+    flag_features: Vec<String>,
+    flag_nightly_feature: Option<String>,
+    flag_all_features: bool,
+    flag_no_default_features: bool,
+    flag_no_deps: bool,
+    flag_document_private_items: bool,
+    flag_jobs: Option<u32>,
+    flag_manifest_path: Option<String>,
+}
+
+impl Options {
+    fn workspace<'a>(&self, config: &'a Config) -> CargoResult<Workspace<'a>> {
+        if let Some(ref path) = self.flag_manifest_path {
+            Workspace::new(&Path::new(path), config)
+        } else {
+            let root = try!(cargo::util::important_paths::find_root_manifest_for_wd(config.cwd()));
+            Workspace::new(&root, config)
+        }
+    }
+
+    /// Run `cargo doc` on `ws`, returning the directory the documentation is
+    /// in.
+    fn build(&self, ws: &Workspace, config: &Config) -> CargoResult<PathBuf> {
+        let mode = CompileMode::Doc { deps: !self.flag_no_deps };
+        let mut compile_opts = try!(CompileOptions::new(config, mode));
+        compile_opts.build_config = try!(BuildConfig::new(config, self.flag_jobs, &self.flag_target, mode));
+        compile_opts.spec = try!(Packages::from_flags(self.flag_all, self.flag_exclude.clone(), self.flag_package.clone()));
+        compile_opts.features = try!(features(&self.flag_features, &self.flag_nightly_feature));
+        compile_opts.all_features = self.flag_all_features;
+        compile_opts.no_default_features = self.flag_no_default_features;
+        if self.flag_document_private_items {
+            compile_opts.local_rustdoc_args = Some(vec!["--document-private-items".to_string()]);
+        }
+        try!(ops::doc(ws, &DocOptions {
+            open_result: false,
+            compile_opts: compile_opts,
+        }));
+        Ok(self.doc_dir(&ws.target_dir().into_path_unlocked()))
+    }
+
+    /// The directory rustdoc writes the documentation to in `target_dir`
+    fn doc_dir(&self, target_dir: &Path) -> PathBuf {
+        match self.flag_target {
+            Some(ref target) => target_dir.join(target).join("doc"),
+            None => target_dir.join("doc"),
+        }
+    }
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let deploy_options: DeployOptions = try!(args.clone().deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+    let max_history = try!(deploy_options.max_history());
+
+    // The version is shown on the badge, and names the folder of releases
+    let ws = options.workspace(config).map_err(|error| println!("couldn't generate workspace: {}", error)).ok();
+    let package = ws.as_ref().and_then(main_package);
+
+    // Releases are published whatever --branch says, under their version
+    let tag = deploy_options.tag();
+    let (branch, path) = match tag {
+        Some(ref tag) => match options.flag_path {
+            Some(ref path) => (tag.clone(), path.clone()),
+            None => match package {
+                Some(package) => {
+                    println!("Publishing the documentation of tag {} as version {}", tag, package.version());
+                    (tag.clone(), format!("v{}", package.version()))
+                }
+                None => {
+                    println!("Publishing the documentation of tag {} under its name", tag);
+                    (tag.clone(), tag.clone())
+                }
+            },
+        },
+        None => match deploy_options.branch() {
+            Some(branch) => {
+                let path = options.flag_path.clone().unwrap_or(branch.clone());
+                (branch, path)
+            }
+            None => return Ok(()),
+        },
+    };
+    let origin = deploy_options.origin();
+
+    let message = deploy_options.message("Automatic Travis documentation build", &branch, config);
+    let clobber_index = options.flag_clobber_index;
+
+    let local_doc_path = match ws {
+        Some(ref ws) if options.flag_build => try!(options.build(ws, config)),
+        Some(ref ws) => options.doc_dir(&ws.target_dir().into_path_unlocked()),
+        None if options.flag_build => return Err(CliError::new(format_err!("couldn't load the workspace to document"), 101)),
+        None => options.doc_dir(Path::new("target")),
+    };
+
+    if options.flag_check_links && local_doc_path.is_dir() {
+        let broken = try!(links::check(&local_doc_path).map_err(|e| CliError::new(e.into(), 101)));
+        for link in &broken {
+            println!("{}: broken link {} ({})", local_doc_path.join(&link.page).display(), link.href, link.reason);
+        }
+        let pages = broken.iter().map(|link| &link.page).collect::<BTreeSet<_>>().len();
+        println!("{} broken link(s) in {} page(s)", broken.len(), pages);
+        if !broken.is_empty() && options.flag_deny_broken_links {
+            return Err(CliError::new(format_err!("not uploading documentation with broken links"), 1));
+        }
+    }
+
+    let prune = PruneOptions {
+        keep_tags: options.flag_keep_tags,
+        max_age: options.flag_max_age,
+        protected: &options.flag_protect,
+        unknown: options.flag_prune_unknown,
+    };
+
+    match doc_upload(&message, &deploy_options.author(), &origin, deploy_options.deploy_branch(), &path,
+                     if tag.is_none() { Some(&branch) } else { None }, tag.as_ref().map(|tag| &tag[..]), ws.as_ref(), &local_doc_path, clobber_index,
+                     if options.flag_prune { Some(&prune) } else { None }, max_history, deploy_options.flag_dry_run) {
+        Ok(..) => Ok(()),
+        Err((string, err)) => Err(CliError::new(err_msg(origin.redact(&string)), err)),
+    }
+}
@@ -0,0 +1,71 @@
+use cargo;
+use cargo::core::Workspace;
+use cargo::util::{CliError, CliResult, Config};
+use docopt::ArgvMap;
+use std::fs;
+use std::path::Path;
+use ci::{self, Project};
+
+pub const USAGE: &'static str = "
+Write a CI configuration running cargo-travis for the workspace: its tests on
+all channels, coverage and documentation uploads.
+
+Usage:
+    cargo travis init [options]
+
+Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+    --travis                     Write a .travis.yml (the default)
+    --github                     Write a GitHub Actions workflow to .github/workflows/ci.yml
+    --force                      Overwrite existing files
+    --manifest-path PATH         Path to the manifest of the workspace
+";
+
+pub fn usage() -> String {
+    USAGE.to_string()
+}
+
+#[derive(Deserialize)]
+pub struct Options {
+    flag_travis: bool,
+    flag_github: bool,
+    flag_force: bool,
+    flag_manifest_path: Option<String>,
+}
+
+pub fn execute(args: ArgvMap, config: &mut Config) -> CliResult {
+    let options: Options = try!(args.deserialize().map_err(|e| CliError::new(e.into(), 1)));
+
+    let ws = if let Some(ref path) = options.flag_manifest_path {
+        try!(Workspace::new(&Path::new(path), config))
+    } else {
+        let root = try!(cargo::util::important_paths::find_root_manifest_for_wd(config.cwd()));
+        try!(Workspace::new(&root, config))
+    };
+    let project = Project::inspect(&ws);
+
+    let mut files = vec![];
+    if options.flag_travis || !options.flag_github {
+        files.push((ws.root().join(".travis.yml"), ci::travis_yml(&project)));
+    }
+    if options.flag_github {
+        files.push((ws.root().join(".github").join("workflows").join("ci.yml"), ci::github_workflow(&project)));
+    }
+
+    // Check everything first, to not write half of the configuration
+    if !options.flag_force {
+        if let Some(&(ref path, _)) = files.iter().find(|&&(ref path, _)| path.exists()) {
+            return Err(CliError::new(format_err!("{} already exists, use --force to overwrite it", path.display()), 1));
+        }
+    }
+
+    for (path, contents) in files {
+        try!(config.shell().status("Writing", path.display().to_string()));
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent).map_err(|e| CliError::new(e.into(), 101)));
+        }
+        try!(fs::write(&path, contents).map_err(|e| CliError::new(e.into(), 101)));
+    }
+    Ok(())
+}
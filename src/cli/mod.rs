@@ -0,0 +1,546 @@
+//! The command line interface of `cargo travis` and of its `cargo-*` aliases.
+//!
+//! Options shared by several subcommands are defined once here, as a section
+//! of the docopt usage string and the struct it deserializes into. Every
+//! subcommand's usage is its own options followed by the shared sections it
+//! needs.
+
+use cargo;
+use cargo::core::compiler::{BuildConfig, CompileMode};
+use cargo::core::{Shell, Workspace};
+use cargo::ops::{CompileFilter, CompileOptions, Packages};
+use cargo::util::{CargoTestError, CliError, CliResult, Config};
+use cargo::CargoResult;
+use docopt::{ArgvMap, Docopt};
+use env_logger;
+use failure::err_msg;
+use std::env;
+use std::path::Path;
+use chrono;
+use deploy::{Author, Remote};
+use {current_commit, rustc_channel};
+
+pub mod bench;
+pub mod check_config;
+pub mod coverage;
+pub mod coverage_upload;
+pub mod coveralls;
+pub mod doc_upload;
+pub mod init;
+
+pub const USAGE: &'static str = "
+Run coverage, upload docs, and more on travis.
+
+Usage:
+    cargo travis <command> [<args>...]
+    cargo travis [options]
+
+Options:
+    -h, --help                   Print this message
+    -V, --version                Print version info and exit
+
+Commands:
+";
+
+pub const GLOBAL_OPTIONS: &'static str = "
+Global Options:
+    --only CHANNEL               Only run when the toolchain is on CHANNEL (stable, beta
+                                 or nightly), and do nothing otherwise
+";
+
+pub const TEST_OPTIONS: &'static str = "
+Test Options:
+    --lib                        Test only this package's library
+    --bin NAME                   Test only the specified binary
+    --bins                       Test all binaries
+    --test NAME                  Test only the specified integration test target
+    --tests                      Test all tests
+    --bench NAME ...             Test only the specified bench target
+    --benches                    Test all benches
+    --all-targets                Test all targets (default)
+    -p SPEC, --package SPEC ...  Package to run tests for
+    --all                        Test all packages in the workspace
+    --exclude SPEC ...           Exclude packages from the test
+    -j N, --jobs N               Number of parallel jobs, defaults to # of CPUs
+    --release                    Build artifacts in release mode, with optimizations
+    --features FEATURES          Space-separated list of features to also build
+    --all-features               Build all available features
+    --no-default-features        Do not build the `default` feature
+    --nightly-feature FEATURE    Also build FEATURE when the toolchain is nightly
+    --target TRIPLE              Build for the target triple
+    --manifest-path PATH         Path to the manifest to build tests for
+    -v, --verbose ...            Use verbose output
+    -q, --quiet                  No output printed to stdout
+    --color WHEN                 Coloring: auto, always, never
+    --no-fail-fast               Run all tests regardless of failure
+    --frozen                     Require Cargo.lock and cache are up to date
+    --locked                     Require Cargo.lock is up to date
+    -Z FLAG ...                  Unstable (nightly-only) flags to Cargo
+";
+
+pub const KCOV_OPTIONS: &'static str = "
+Kcov Options:
+    --exclude-pattern PATTERN    Comma-separated path patterns to exclude from the report
+    --junit PATH                 Write a JUnit XML report of the test results to PATH
+    --message-format FMT         Output format: human, or json to finish with a JSON
+                                 description of the run [default: human]
+    --kcov-build-location PATH   Path to the directory in which to build kcov (into a new folder)
+                                 [default: target] -- kcov ends up in target/kcov-master
+";
+
+pub const DEPLOY_OPTIONS: &'static str = "
+Deploy Options:
+    --branch NAME ...            Only publish for these branches
+                                 Defaults to only the `master` branch
+    --token TOKEN                Use the specified GitHub token to publish
+                                 If unspecified, checks $GH_TOKEN then attempts to use SSH endpoint
+    --origin URL                 Push to this git remote, any URL or path git accepts. The token
+                                 is used for HTTPS URLs. Overrides --repo and --host
+    --repo SLUG                  Push to the repository OWNER/NAME of --host
+                                 Defaults to $TRAVIS_REPO_SLUG
+    --host HOST                  The git server hosting --repo [default: github.com]
+    --message MESSAGE            The message to include in the commit, where {branch}, {commit},
+                                 {build}, {version} and {date} are replaced with the branch,
+                                 commit and build number being built, the version of the
+                                 package and the current date
+    --author-name NAME           The name to make the commit with
+    --author-email EMAIL         The email to make the commit with
+    --deploy BRANCH              Deploy to the given branch [default: gh-pages]
+    --dry-run                    Commit to the deploy branch in target/doc-upload, but don't push it
+    --single-commit              Replace the history of the deploy branch with a single commit
+                                 (force-pushed)
+    --max-history N              Only keep the N latest commits of the deploy branch
+                                 (force-pushed)
+";
+
+/// Options of the commands building and running the tests, see `TEST_OPTIONS`.
+#[derive(Deserialize)]
+pub struct TestOptions {
+    pub arg_args: Vec<String>,
+    flag_features: Vec<String>,
+    flag_nightly_feature: Option<String>,
+    flag_all_features: bool,
+    flag_jobs: Option<u32>,
+    flag_manifest_path: Option<String>,
+    flag_no_default_features: bool,
+    flag_package: Vec<String>,
+    flag_target: Option<String>,
+    flag_lib: bool,
+    flag_bin: Vec<String>,
+    flag_bins: bool,
+    flag_test: Vec<String>,
+    flag_tests: bool,
+    flag_bench: Vec<String>,
+    flag_benches: bool,
+    flag_all_targets: bool,
+    flag_verbose: u32,
+    flag_quiet: Option<bool>,
+    flag_color: Option<String>,
+    pub flag_release: bool,
+    pub flag_no_fail_fast: bool,
+    flag_frozen: bool,
+    flag_locked: bool,
+    flag_all: bool,
+    flag_exclude: Vec<String>,
+    #[serde(rename = "flag_Z")]
+    flag_z: Vec<String>,
+}
+
+impl TestOptions {
+    /// Apply the verbosity, color, lockfile and unstable options to `config`.
+    pub fn configure(&self, config: &mut Config) -> CargoResult<()> {
+        config.configure(self.flag_verbose,
+                         self.flag_quiet,
+                         &self.flag_color,
+                         self.flag_frozen,
+                         self.flag_locked,
+                         &None,
+                         &self.flag_z)
+    }
+
+    pub fn workspace<'a>(&self, config: &'a Config) -> CargoResult<Workspace<'a>> {
+        if let Some(ref path) = self.flag_manifest_path {
+            Workspace::new(&Path::new(path), config)
+        } else {
+            let root = try!(cargo::util::important_paths::find_root_manifest_for_wd(config.cwd()));
+            Workspace::new(&root, config)
+        }
+    }
+
+    pub fn compile_options<'a>(&self, config: &'a Config, mode: CompileMode) -> CargoResult<CompileOptions<'a>> {
+        let filter = CompileFilter::new(
+            self.flag_lib,
+            self.flag_bin.clone(),
+            self.flag_bins,
+            self.flag_test.clone(),
+            self.flag_tests,
+            vec![],
+            false,
+            self.flag_bench.clone(),
+            self.flag_benches,
+            self.flag_all_targets
+        );
+
+        let spec = try!(Packages::from_flags(self.flag_all, self.flag_exclude.clone(), self.flag_package.clone()));
+
+        let mut build_config = try!(BuildConfig::new(config, self.flag_jobs, &self.flag_target, mode));
+        build_config.release = self.flag_release;
+
+        Ok(CompileOptions {
+            config: config,
+            build_config: build_config,
+            all_features: self.flag_all_features,
+            features: try!(features(&self.flag_features, &self.flag_nightly_feature)),
+            no_default_features: self.flag_no_default_features,
+            spec: spec,
+            filter: filter,
+            target_rustdoc_args: None,
+            target_rustc_args: None,
+            local_rustdoc_args: None,
+            export_dir: None,
+        })
+    }
+}
+
+/// The `--features` to build, and the `--nightly-feature` on nightly.
+pub fn features(features: &[String], nightly_feature: &Option<String>) -> CargoResult<Vec<String>> {
+    let mut features = features.to_vec();
+    if let Some(ref feature) = *nightly_feature {
+        if try!(rustc_channel()) == "nightly" {
+            features.push(feature.clone());
+        }
+    }
+    Ok(features)
+}
+
+/// Options of the commands running the tests under kcov, see `KCOV_OPTIONS`.
+#[derive(Deserialize)]
+pub struct KcovOptions {
+    pub flag_exclude_pattern: Option<String>,
+    pub flag_junit: Option<String>,
+    pub flag_message_format: String,
+    pub flag_kcov_build_location: String,
+}
+
+impl KcovOptions {
+    pub fn check(&self) -> CliResult {
+        if self.flag_message_format != "human" && self.flag_message_format != "json" {
+            return Err(CliError::new(format_err!("unknown message format `{}`", self.flag_message_format), 1));
+        }
+        Ok(())
+    }
+}
+
+/// Options of the commands publishing to the deploy branch, see
+/// `DEPLOY_OPTIONS`.
+#[derive(Deserialize)]
+pub struct DeployOptions {
+    flag_branch: Vec<String>,
+    flag_token: Option<String>,
+    flag_origin: Option<String>,
+    flag_repo: Option<String>,
+    flag_host: String,
+    flag_message: Option<String>,
+    flag_author_name: Option<String>,
+    flag_author_email: Option<String>,
+    flag_deploy: String,
+    pub flag_dry_run: bool,
+    flag_single_commit: bool,
+    flag_max_history: Option<usize>,
+}
+
+impl DeployOptions {
+    /// The branch being built, or `None` if it shouldn't be published.
+    pub fn branch(&self) -> Option<String> {
+        let branches = if self.flag_branch.is_empty() {
+            vec!["master".to_string()]
+        } else {
+            self.flag_branch.clone()
+        };
+
+        let branch = env::var("TRAVIS_BRANCH").expect("$TRAVIS_BRANCH not set");
+        if !branches.contains(&branch) {
+            println!("Skipping branch {}", branch);
+            return None;
+        }
+
+        let pull_request = env::var("TRAVIS_PULL_REQUEST").expect("$TRAVIS_PULL_REQUEST not set");
+        if pull_request != "false" {
+            println!("Skipping PR");
+            return None;
+        }
+
+        Some(branch)
+    }
+
+    /// The tag being built, if this is the build of a tag and it should be
+    /// published.
+    pub fn tag(&self) -> Option<String> {
+        let tag = match env::var("TRAVIS_TAG") {
+            Ok(ref tag) if !tag.is_empty() => tag.clone(),
+            _ => return None,
+        };
+        if env::var("TRAVIS_PULL_REQUEST").ok().map_or(false, |pull_request| pull_request != "false") {
+            println!("Skipping PR");
+            return None;
+        }
+        Some(tag)
+    }
+
+    /// The remote to push to, along with the token to authenticate with.
+    pub fn origin(&self) -> Remote {
+        let token = self.flag_token.clone().or(env::var("GH_TOKEN").ok());
+        if let Some(ref origin) = self.flag_origin {
+            return Remote {
+                url: origin.clone(),
+                token: token,
+            };
+        }
+
+        let slug = self.flag_repo.clone().unwrap_or_else(|| {
+            env::var("TRAVIS_REPO_SLUG").expect("$TRAVIS_REPO_SLUG not set, pass --repo or --origin")
+        });
+        if token.is_some() {
+            Remote {
+                url: format!("https://{}/{}.git", self.flag_host, slug),
+                token: token,
+            }
+        } else {
+            eprintln!("GitHub Personal Access Token was not provided in $GH_TOKEN or --token");
+            eprintln!("Falling back to using the SSH endpoint");
+            Remote {
+                url: format!("git@{}:{}.git", self.flag_host, slug),
+                token: None,
+            }
+        }
+    }
+
+    pub fn deploy_branch(&self) -> &str {
+        &self.flag_deploy
+    }
+
+    /// The commit message, `--message` or `default`, with its placeholders
+    /// filled in.
+    pub fn message(&self, default: &str, branch: &str, config: &Config) -> String {
+        let template = self.flag_message.clone().unwrap_or(default.to_string());
+        let mut message = template.replace("{branch}", branch)
+            .replace("{build}", &env::var("TRAVIS_BUILD_NUMBER").unwrap_or("unknown".to_string()))
+            .replace("{date}", &chrono::Utc::now().format("%Y-%m-%d").to_string());
+        if message.contains("{commit}") {
+            message = message.replace("{commit}", &current_commit());
+        }
+        if message.contains("{version}") {
+            let version = cargo::util::important_paths::find_root_manifest_for_wd(config.cwd())
+                .and_then(|root| Workspace::new(&root, config).and_then(|ws| Ok(ws.current()?.version().to_string())))
+                .unwrap_or("unknown".to_string());
+            message = message.replace("{version}", &version);
+        }
+        message
+    }
+
+    pub fn author(&self) -> Author {
+        Author {
+            name: self.flag_author_name.clone(),
+            email: self.flag_author_email.clone(),
+        }
+    }
+
+    /// How many commits to keep in the history of the deploy branch, if it's
+    /// to be rewritten.
+    pub fn max_history(&self) -> Result<Option<usize>, CliError> {
+        if self.flag_single_commit {
+            return Ok(Some(1));
+        }
+        match self.flag_max_history {
+            Some(0) => Err(CliError::new(format_err!("--max-history must be at least 1"), 1)),
+            max_history => Ok(max_history),
+        }
+    }
+}
+
+/// Turn the error of a failed test run into the error to exit with
+pub fn test_error(err: CargoTestError, what: &str) -> CliError {
+    match err.exit.as_ref().and_then(|e| e.code()) {
+        Some(i) => CliError::new(err_msg(format!("{} failed", what)), i),
+        None => CliError::new(err.into(), 101)
+    }
+}
+
+/// A subcommand of `cargo travis`.
+pub struct Command {
+    pub name: &'static str,
+    pub about: &'static str,
+    /// The full docopt usage string
+    pub usage: fn() -> String,
+    pub execute: fn(ArgvMap, &mut Config) -> CliResult,
+}
+
+pub const COMMANDS: &'static [Command] = &[
+    Command {
+        name: "coverage",
+        about: "Record coverage of `cargo test`",
+        usage: coverage::usage,
+        execute: coverage::execute,
+    },
+    Command {
+        name: "coveralls",
+        about: "Record coverage of `cargo test` and send it to coveralls.io",
+        usage: coveralls::usage,
+        execute: coveralls::execute,
+    },
+    Command {
+        name: "coverage-upload",
+        about: "Upload the coverage report to GitHub pages",
+        usage: coverage_upload::usage,
+        execute: coverage_upload::execute,
+    },
+    Command {
+        name: "doc-upload",
+        about: "Upload built rustdoc documentation to GitHub pages",
+        usage: doc_upload::usage,
+        execute: doc_upload::execute,
+    },
+    Command {
+        name: "travis-bench",
+        about: "Run the benchmarks and compare them with a previous run",
+        usage: bench::usage,
+        execute: bench::execute,
+    },
+    Command {
+        name: "init",
+        about: "Write a CI configuration using these commands",
+        usage: init::usage,
+        execute: init::execute,
+    },
+    Command {
+        name: "check-config",
+        about: "Check that .travis.yml has what these commands need",
+        usage: check_config::usage,
+        execute: check_config::execute,
+    },
+];
+
+fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name || command.name == format!("travis-{}", name))
+}
+
+fn usage() -> String {
+    let mut usage = USAGE.to_string();
+    for command in COMMANDS {
+        usage.push_str(&format!("    {:<28} {}\n", command.name.trim_start_matches("travis-"), command.about));
+    }
+    usage
+}
+
+/// `args` with the value of `--token` and the credentials of URLs masked, to
+/// log them.
+fn redact_args(args: &[String]) -> Vec<String> {
+    let mut redacted = vec![];
+    let mut token_next = false;
+    for arg in args {
+        if token_next {
+            redacted.push("***".to_string());
+            token_next = false;
+        } else if arg.starts_with("--token=") {
+            redacted.push("--token=***".to_string());
+        } else {
+            token_next = arg == "--token";
+            let url = arg.trim_start_matches("--origin=");
+            redacted.push(Remote { url: url.to_string(), token: None }.redact(arg));
+        }
+    }
+    redacted
+}
+
+fn run_command(command: &Command, args: Vec<String>, config: &mut Config) -> CliResult {
+    debug!("executing; cmd=cargo-{}; args={:?}", command.name, redact_args(&args));
+
+    let docopt = Docopt::new(format!("{}{}", (command.usage)(), GLOBAL_OPTIONS)).unwrap()
+        .argv(args.iter().map(|s| &s[..]))
+        .help(true);
+
+    let args = docopt.parse().map_err(|e| {
+        let code = if e.fatal() {1} else {0};
+        CliError::new(e.into(), code)
+    })?;
+
+    if args.get_bool("--version") {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let only = args.get_str("--only");
+    if !only.is_empty() {
+        if !["stable", "beta", "nightly"].contains(&only) {
+            return Err(CliError::new(format_err!("unknown toolchain channel `{}`", only), 1));
+        }
+        let channel = try!(rustc_channel());
+        if channel != only {
+            println!("Skipping, the toolchain is on {}, not {}", channel, only);
+            return Ok(());
+        }
+    }
+
+    (command.execute)(args, config)
+}
+
+fn run(name: Option<&str>) {
+    env_logger::init().unwrap();
+    let mut config = match Config::default() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+             let mut shell = Shell::new();
+             cargo::exit_with_error(e.into(), &mut shell)
+        }
+    };
+    let result = (|| {
+        let args: Vec<_> = try!(env::args_os()
+            .map(|s| {
+                s.into_string().map_err(|s| {
+                    format_err!("invalid unicode in argument: {:?}", s)
+                })
+            })
+            .collect());
+
+        let command = match name {
+            Some(name) => find_command(name),
+            // cargo runs us as `cargo-travis travis <command> ...`
+            None => args.get(2).and_then(|name| find_command(name)),
+        };
+
+        match command {
+            Some(command) => run_command(command, args, &mut config),
+            None => {
+                let docopt = Docopt::new(usage()).unwrap()
+                    .argv(args.iter().map(|s| &s[..]))
+                    .help(true)
+                    .version(Some(format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))));
+                let args = docopt.parse().map_err(|e| {
+                    let code = if e.fatal() {1} else {0};
+                    CliError::new(e.into(), code)
+                })?;
+                match args.get_str("<command>") {
+                    "" => {
+                        print!("{}", usage());
+                        Ok(())
+                    }
+                    name => Err(CliError::new(format_err!("no such subcommand: `{}`\n{}", name, usage()), 1)),
+                }
+            }
+        }
+    })();
+    match result {
+        Err(e) => cargo::exit_with_error(e, &mut *config.shell()),
+        Ok(()) => {}
+    }
+}
+
+/// Entry point of `cargo travis`
+pub fn main() {
+    run(None)
+}
+
+/// Entry point of the `cargo-NAME` aliases of `cargo travis NAME`
+pub fn alias_main(name: &str) {
+    run(Some(name))
+}
@@ -0,0 +1,87 @@
+use cargo::CargoResult;
+use md5;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Value of the `attr="..."` attribute in an XML tag
+fn attribute<'a>(tag: &'a str, attr: &str) -> Option<&'a str> {
+    let needle = format!(" {}=\"", attr);
+    let start = tag.find(&needle)? + needle.len();
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&quot;", "\"").replace("&apos;", "'").replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+/// Per-line hit counts of every file in a kcov `cobertura.xml`.
+///
+/// kcov writes one tag per line, which is all this expects.
+fn parse_cobertura(xml: &str) -> BTreeMap<PathBuf, BTreeMap<usize, u64>> {
+    let mut files = BTreeMap::new();
+    let mut source = PathBuf::new();
+    let mut current: Option<PathBuf> = None;
+
+    for line in xml.lines() {
+        let line = line.trim();
+        if line.starts_with("<source>") && line.ends_with("</source>") {
+            source = PathBuf::from(unescape(&line["<source>".len()..line.len() - "</source>".len()]));
+        } else if line.starts_with("<class ") {
+            current = attribute(line, "filename").map(|filename| source.join(unescape(filename)));
+        } else if line.starts_with("<line ") {
+            let file = match current {
+                Some(ref file) => file,
+                None => continue,
+            };
+            let number: Option<usize> = attribute(line, "number").and_then(|number| number.parse().ok());
+            let hits: Option<u64> = attribute(line, "hits").and_then(|hits| hits.parse().ok());
+            if let (Some(number), Some(hits)) = (number, hits) {
+                let lines = files.entry(file.clone()).or_insert_with(BTreeMap::new);
+                *lines.entry(number).or_insert(0) += hits;
+            }
+        }
+    }
+
+    files
+}
+
+/// Build the coveralls.io job payload for the merged kcov results in
+/// `merge_dir`, the same way kcov does when given `--coveralls-id`.
+///
+/// Source file names are made relative to `root`, which should be the root of
+/// the repository.
+pub fn payload(merge_dir: &Path, root: &Path, job_id: &str) -> CargoResult<Value> {
+    let cobertura = merge_dir.join("kcov-merged").join("cobertura.xml");
+    let xml = fs::read_to_string(&cobertura)
+        .map_err(|err| format_err!("couldn't read {}: {}", cobertura.display(), err))?;
+
+    let mut source_files = vec![];
+    for (path, lines) in parse_cobertura(&xml) {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) => {
+                println!("couldn't read {}, skipping it: {}", path.display(), err);
+                continue;
+            }
+        };
+        let coverage: Vec<Value> = (1..source.lines().count() + 1)
+            .map(|number| lines.get(&number).map_or(Value::Null, |&hits| json!(hits)))
+            .collect();
+        let name = path.strip_prefix(root).unwrap_or(&path);
+
+        source_files.push(json!({
+            "name": name.to_string_lossy(),
+            "source_digest": format!("{:x}", md5::compute(source.as_bytes())),
+            "coverage": coverage,
+        }));
+    }
+
+    Ok(json!({
+        "service_job_id": job_id,
+        "service_name": "travis-ci",
+        "source_files": source_files,
+    }))
+}
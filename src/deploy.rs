@@ -0,0 +1,440 @@
+use cargo::CargoResult;
+use failure;
+use git2::{self, Cred, CredentialType, DiffStatsFormat, FetchOptions, IndexAddOption, Oid, PushOptions,
+           RemoteCallbacks, Repository, Signature};
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// How many times the credentials callback is called before giving up, as
+/// libgit2 keeps asking as long as the remote refuses them.
+const CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// Identity the commits are made with. What isn't set is taken from the git
+/// configuration, if any.
+#[derive(Clone, Debug, Default)]
+pub struct Author {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+/// The remote to publish to. The token is given to libgit2 when it asks for
+/// credentials, and never makes it into the URL.
+#[derive(Clone)]
+pub struct Remote {
+    pub url: String,
+    pub token: Option<String>,
+}
+
+impl Remote {
+    /// Mask the token, and the credentials of the URL if it has some, in
+    /// `text`, so that it can be printed in public build logs.
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if let Some(ref token) = self.token {
+            if !token.is_empty() {
+                text = text.replace(&token[..], "***");
+            }
+        }
+        if let Some(credentials) = self.credentials() {
+            // Only where the URL is quoted, e.g. `git@` is everywhere in the
+            // errors about `ssh://git@host/...`
+            text = text.replace(&format!("{}@", credentials), "***@");
+        }
+        text
+    }
+
+    /// The user information of the URL, e.g. `TOKEN` in
+    /// `https://TOKEN@host/...`
+    fn credentials(&self) -> Option<&str> {
+        let scheme_end = self.url.find("://")?;
+        let rest = &self.url[scheme_end + 3..];
+        let host_end = rest.find('/').unwrap_or(rest.len());
+        rest[..host_end].rfind('@').map(|at| &rest[..at])
+    }
+}
+
+impl fmt::Debug for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Remote")
+            .field("url", &self.redact(&self.url))
+            .field("token", &self.token.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+/// A checkout of the deploy branch (e.g. `gh-pages`) of a remote.
+pub struct DeployBranch {
+    repo: Repository,
+    remote: Remote,
+    branch: String,
+    author: Author,
+    /// How many commits of the branch are fetched, or all of them
+    depth: Option<usize>,
+}
+
+/// Callbacks authenticating with the token, or the one in the URL, for HTTPS
+/// remotes, or with the SSH agent or key of the user otherwise.
+fn callbacks<'a>(token: Option<&'a str>) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("the remote refused the credentials"));
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            // GitHub and others accept this password along with the token as
+            // user name, e.g. for `https://TOKEN@host/...`
+            match token.or(username) {
+                Some(token) => return Cred::userpass_plaintext(token, "x-oauth-basic"),
+                None => {
+                    let config = try!(git2::Config::open_default());
+                    return Cred::credential_helper(&config, url, None);
+                }
+            }
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            let username = username.unwrap_or("git");
+            if attempts == 1 {
+                return Cred::ssh_key_from_agent(username);
+            }
+            if let Some(home) = env::var_os("HOME") {
+                return Cred::ssh_key(username, None, &Path::new(&home).join(".ssh").join("id_rsa"), None);
+            }
+        }
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username.unwrap_or("git"));
+        }
+        Err(git2::Error::from_str("no credentials available for the remote"))
+    });
+    callbacks
+}
+
+impl DeployBranch {
+    /// Check out `branch` of `remote` into `path`, or start it from scratch if
+    /// the remote doesn't have it yet.
+    ///
+    /// An existing checkout in `path` is assumed to be ours and reused, but
+    /// fetched again all the same: it may have been restored from the cache of
+    /// an older build, and force-pushing it would lose what was uploaded since.
+    ///
+    /// Only the `depth` latest commits of the branch are fetched, with git, or
+    /// its whole history with libgit2 if git isn't installed or `depth` isn't
+    /// given.
+    pub fn checkout(remote: &Remote, branch: &str, path: &Path, author: &Author, depth: Option<usize>) -> CargoResult<DeployBranch> {
+        let deploy = DeployBranch {
+            repo: if path.exists() {
+                try!(Repository::open(path))
+            } else {
+                try!(Repository::init(path))
+            },
+            remote: remote.clone(),
+            branch: branch.to_string(),
+            author: author.clone(),
+            depth: depth,
+        };
+        try!(deploy.fetch());
+        Ok(deploy)
+    }
+
+    /// Fetch the deploy branch and check it out, discarding local changes.
+    ///
+    /// libgit2 can't do shallow fetches, and the history of the deploy branch
+    /// can be huge, so the fetch is left to git when it's installed. This
+    /// trades spawning git, with the token in its environment, for not
+    /// downloading every documentation ever uploaded.
+    pub fn fetch(&self) -> CargoResult<()> {
+        let tracking = format!("refs/remotes/origin/{}", self.branch);
+        let refspec = format!("+refs/heads/{}:{}", self.branch, tracking);
+
+        println!("Fetching {} from the remote", self.branch);
+        let fetched = match self.depth {
+            Some(depth) => try!(self.shallow_fetch(&refspec, depth)),
+            None => false,
+        };
+        if !fetched {
+            if self.depth.is_some() {
+                println!("git isn't installed, fetching the whole history of {}", self.branch);
+            }
+            try!(self.libgit2_fetch(&refspec));
+        }
+
+        let head = format!("refs/heads/{}", self.branch);
+        match self.repo.find_reference(&tracking) {
+            Ok(reference) => {
+                let commit = try!(reference.peel_to_commit());
+                try!(self.repo.reference(&head, commit.id(), true, "cargo-travis: fetch"));
+                try!(self.repo.set_head(&head));
+                try!(self.repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force().remove_untracked(true))));
+            }
+            Err(ref err) if err.code() == git2::ErrorCode::NotFound => {
+                println!("The remote doesn't have {} yet, creating it", self.branch);
+                try!(self.repo.set_head(&head));
+            }
+            Err(err) => return Err(err.into()),
+        }
+        Ok(())
+    }
+
+    /// Fetch `refspec` with libgit2, which fetches the whole history.
+    fn libgit2_fetch(&self, refspec: &str) -> CargoResult<()> {
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks(self.token()));
+        let mut remote = try!(self.repo.remote_anonymous(&self.remote.url).map_err(|err| self.error(err)));
+        remote.fetch(&[refspec], Some(&mut fetch_options), None)
+            .map_err(|err| format_err!("couldn't fetch {}: {}", self.branch, self.remote.redact(err.message())))
+    }
+
+    /// A git command run in the checkout, authenticating with the token.
+    ///
+    /// The token is given to git by a credential helper reading it from the
+    /// environment, to keep it out of the command line.
+    fn git(&self) -> Command {
+        let mut git = Command::new("git");
+        git.arg("-C").arg(self.path()).env("GIT_TERMINAL_PROMPT", "0");
+        if let Some(token) = self.token() {
+            git.env("CARGO_TRAVIS_TOKEN", token)
+                .args(&["-c", "credential.helper="])
+                .args(&["-c", "credential.helper=!f() { echo username=$CARGO_TRAVIS_TOKEN; echo password=x-oauth-basic; }; f"]);
+        }
+        git
+    }
+
+    /// Fetch the `depth` latest commits of `refspec` with git, if it's
+    /// installed. A missing branch isn't an error, like with libgit2.
+    fn shallow_fetch(&self, refspec: &str, depth: usize) -> CargoResult<bool> {
+        let mut git = self.git();
+        git.args(&["fetch", "--quiet", "--no-tags", &format!("--depth={}", depth), &self.remote.url, refspec]);
+
+        let output = match git.output() {
+            Ok(output) => output,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() && !stderr.contains("couldn't find remote ref") {
+            return Err(format_err!("couldn't fetch {}: {}", self.branch, self.remote.redact(stderr.trim())));
+        }
+        Ok(true)
+    }
+
+    fn token(&self) -> Option<&str> {
+        self.remote.token.as_ref().map(|token| &token[..])
+    }
+
+    /// The error of an operation on the remote, which may mention its URL
+    fn error(&self, err: git2::Error) -> failure::Error {
+        format_err!("{}", self.remote.redact(err.message()))
+    }
+
+    /// The working directory of the checkout
+    pub fn path(&self) -> &Path {
+        self.repo.workdir().expect("the deploy branch checkout isn't bare")
+    }
+
+    fn signature(&self) -> CargoResult<Signature<'static>> {
+        let config = try!(self.repo.config());
+        let name = self.author.name.clone()
+            .or_else(|| config.get_string("user.name").ok())
+            .unwrap_or_else(|| "cargo-travis".to_string());
+        let email = self.author.email.clone()
+            .or_else(|| config.get_string("user.email").ok())
+            .unwrap_or_else(|| "cargo-travis@users.noreply.github.com".to_string());
+        Ok(try!(Signature::now(&name, &email)))
+    }
+
+    /// Commit everything in the working directory, including deletions.
+    ///
+    /// Returns `None` if nothing changed.
+    pub fn commit(&self, message: &str) -> CargoResult<Option<Oid>> {
+        let mut index = try!(self.repo.index());
+        try!(index.add_all(&["*"], IndexAddOption::DEFAULT, None));
+        try!(index.update_all(&["*"], None));
+        try!(index.write());
+        let tree = try!(self.repo.find_tree(try!(index.write_tree())));
+
+        let parent = match self.repo.head() {
+            Ok(head) => Some(try!(head.peel_to_commit())),
+            Err(_) => None,
+        };
+        if parent.as_ref().map_or(false, |parent| parent.tree_id() == tree.id()) {
+            return Ok(None);
+        }
+
+        let signature = try!(self.signature());
+        let parents: Vec<_> = parent.iter().collect();
+        let oid = try!(self.repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents));
+        Ok(Some(oid))
+    }
+
+    /// Rewrite the history of the branch to only keep its `max` latest commits,
+    /// the oldest of which becomes a root commit. Returns the new head.
+    pub fn truncate_history(&self, max: usize) -> CargoResult<Oid> {
+        let head = try!(try!(self.repo.head()).peel_to_commit());
+        let mut kept = vec![head];
+        while kept.len() < max {
+            let parent = match kept.last().unwrap().parent(0) {
+                Ok(parent) => parent,
+                Err(_) => break,
+            };
+            kept.push(parent);
+        }
+        if kept.last().unwrap().parents().next().is_none() {
+            return Ok(kept[0].id());
+        }
+
+        let mut parent = None;
+        for commit in kept.iter().rev() {
+            let oid = {
+                let parents: Vec<_> = parent.iter().collect();
+                try!(self.repo.commit(None, &commit.author(), &commit.committer(), commit.message().unwrap_or(""),
+                                      &try!(commit.tree()), &parents))
+            };
+            parent = Some(try!(self.repo.find_commit(oid)));
+        }
+        let head = parent.unwrap().id();
+        try!(self.repo.reference(&format!("refs/heads/{}", self.branch), head, true, "cargo-travis: truncate history"));
+        Ok(head)
+    }
+
+    /// Print the message and the changed files of `commit`
+    pub fn show(&self, commit: Oid) -> CargoResult<()> {
+        let commit = try!(self.repo.find_commit(commit));
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(try!(parent.tree())),
+            Err(_) => None,
+        };
+        let diff = try!(self.repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&try!(commit.tree())), None));
+        let stats = try!(try!(diff.stats()).to_buf(DiffStatsFormat::FULL, 80));
+        println!("{}\n", commit.summary().unwrap_or(""));
+        print!("{}", String::from_utf8_lossy(&stats));
+        Ok(())
+    }
+
+    /// Names of the branches and tags of the remote.
+    pub fn remote_refs(&self) -> CargoResult<Vec<String>> {
+        let mut remote = try!(self.repo.remote_anonymous(&self.remote.url).map_err(|err| self.error(err)));
+        let connection = try!(remote.connect_auth(git2::Direction::Fetch, Some(callbacks(self.token())), None)
+            .map_err(|err| format_err!("couldn't connect to the remote: {}", self.remote.redact(err.message()))));
+        let heads = try!(connection.list().map_err(|err| self.error(err)));
+        Ok(heads.iter()
+            .map(|head| head.name().trim_end_matches("^{}"))
+            .filter_map(|name| {
+                if name.starts_with("refs/heads/") {
+                    Some(name["refs/heads/".len()..].to_string())
+                } else if name.starts_with("refs/tags/") {
+                    Some(name["refs/tags/".len()..].to_string())
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// Push the deploy branch to the remote, replacing its history if `force`
+    /// is set.
+    ///
+    /// Returns `false` if the push was rejected because the remote branch has
+    /// commits we don't, i.e. it needs to be fetched again first. A forced
+    /// push is only made as long as the remote branch is still where it was
+    /// last fetched, so that the documentation pushed by others in the
+    /// meantime isn't overwritten.
+    pub fn push(&self, force: bool) -> CargoResult<bool> {
+        let pushed = try!(self.push_branch(force));
+        if pushed {
+            // The remote branch is where we left it, as far as the next push
+            // is concerned
+            let head = try!(self.repo.refname_to_id(&format!("refs/heads/{}", self.branch)));
+            try!(self.repo.reference(&format!("refs/remotes/origin/{}", self.branch), head, true, "cargo-travis: push"));
+        }
+        Ok(pushed)
+    }
+
+    fn push_branch(&self, force: bool) -> CargoResult<bool> {
+        let fetched = match self.repo.find_reference(&format!("refs/remotes/origin/{}", self.branch)) {
+            Ok(reference) => reference.target(),
+            Err(ref err) if err.code() == git2::ErrorCode::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        // libgit2 can't push from a shallow repository either
+        if self.repo.is_shallow() {
+            return self.git_push(force, fetched);
+        }
+
+        let refspec = format!("{}refs/heads/{1}:refs/heads/{1}", if force { "+" } else { "" }, self.branch);
+        if force {
+            // libgit2 has no lease, the remote branch is fetched again right
+            // before pushing instead
+            let lease = format!("refs/cargo-travis/lease/{}", self.branch);
+            if let Ok(mut reference) = self.repo.find_reference(&lease) {
+                try!(reference.delete());
+            }
+            try!(self.libgit2_fetch(&format!("+refs/heads/{}:{}", self.branch, lease)));
+            if self.repo.find_reference(&lease).ok().and_then(|reference| reference.target()) != fetched {
+                return Ok(false);
+            }
+        }
+        let rejection = RefCell::new(None);
+        {
+            let mut callbacks = callbacks(self.token());
+            callbacks.push_update_reference(|_, status| {
+                *rejection.borrow_mut() = status.map(|status| status.to_string());
+                Ok(())
+            });
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(callbacks);
+
+            println!("Pushing {} to the remote", self.branch);
+            let mut remote = try!(self.repo.remote_anonymous(&self.remote.url).map_err(|err| self.error(err)));
+            match remote.push(&[&refspec], Some(&mut push_options)) {
+                Ok(()) => {}
+                // Local remotes report it as an error rather than as a status
+                Err(ref err) if err.code() == git2::ErrorCode::NotFastForward => return Ok(false),
+                Err(err) => return Err(format_err!("couldn't push {}: {}", self.branch, self.remote.redact(err.message()))),
+            }
+        }
+        self.push_status(rejection.into_inner())
+    }
+
+    /// Push the deploy branch with git, see `push`. A forced push is leased on
+    /// the `fetched` commit of the remote branch, or on its absence.
+    fn git_push(&self, force: bool, fetched: Option<git2::Oid>) -> CargoResult<bool> {
+        println!("Pushing {} to the remote", self.branch);
+        let mut git = self.git();
+        git.args(&["push", "--porcelain"]);
+        if force {
+            let expected = fetched.map(|oid| oid.to_string()).unwrap_or_default();
+            git.arg(format!("--force-with-lease=refs/heads/{}:{}", self.branch, expected));
+        }
+        git.arg(&self.remote.url).arg(format!("refs/heads/{0}:refs/heads/{0}", self.branch));
+        let output = try!(git.output());
+        if output.status.success() {
+            return Ok(true);
+        }
+
+        // With --porcelain, the ref is reported as `!<TAB>FROM:TO<TAB>[rejected] (REASON)`
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.lines().find(|line| line.starts_with('!')) {
+            Some(line) => self.push_status(Some(line.splitn(3, '\t').nth(2).unwrap_or(line).to_string())),
+            None => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(format_err!("couldn't push {}: {}", self.branch, self.remote.redact(stderr.trim())))
+            }
+        }
+    }
+
+    /// Whether the push succeeded given why the remote rejected it, if it did.
+    fn push_status(&self, rejection: Option<String>) -> CargoResult<bool> {
+        match rejection {
+            None => Ok(true),
+            Some(ref status) if status.contains("fast-forward") || status.contains("fetch first")
+                || status.contains("stale info") => Ok(false),
+            Some(status) => Err(format_err!("the remote rejected {}: {}", self.branch, self.remote.redact(&status))),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How many of the latest entries of each branch are drawn in the chart
+const CHART_ENTRIES: usize = 100;
+
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 200.0;
+const CHART_MARGIN: f64 = 30.0;
+
+const COLORS: &[&str] = &["#4d76ae", "#e05d44", "#4c1", "#dfb317", "#9f9f9f", "#fe7d37", "#a4a61d"];
+
+/// Coverage of one upload, as stored in `coverage-history.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub commit: String,
+    pub date: String,
+    pub branch: String,
+    pub total: f64,
+    #[serde(default)]
+    pub packages: BTreeMap<String, f64>,
+}
+
+/// Read the history, which is empty if the file doesn't exist yet.
+pub fn load(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save(path: &Path, entries: &[HistoryEntry]) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(entries).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(path, json)
+}
+
+/// Render the total coverage over time as an SVG line chart, with one line
+/// per branch.
+pub fn trend_svg(entries: &[HistoryEntry]) -> String {
+    let mut branches: BTreeMap<&str, Vec<&HistoryEntry>> = BTreeMap::new();
+    for entry in entries {
+        branches.entry(&entry.branch).or_insert_with(Vec::new).push(entry);
+    }
+    for branch_entries in branches.values_mut() {
+        let skip = branch_entries.len().saturating_sub(CHART_ENTRIES);
+        branch_entries.drain(..skip);
+    }
+
+    let longest = branches.values().map(|branch_entries| branch_entries.len()).max().unwrap_or(0);
+    let plot_width = CHART_WIDTH - 2.0 * CHART_MARGIN;
+    let plot_height = CHART_HEIGHT - 2.0 * CHART_MARGIN;
+    let x = |i: usize| CHART_MARGIN + if longest > 1 { plot_width * i as f64 / (longest - 1) as f64 } else { 0.0 };
+    let y = |percent: f64| CHART_MARGIN + plot_height * (100.0 - percent.max(0.0).min(100.0)) / 100.0;
+
+    let mut svg = String::new();
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+         font-family=\"DejaVu Sans,Verdana,Geneva,sans-serif\" font-size=\"10\">\n",
+        w = CHART_WIDTH, h = CHART_HEIGHT));
+    svg.push_str("  <rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>\n");
+
+    // Horizontal grid, every 25%
+    for percent in &[0.0, 25.0, 50.0, 75.0, 100.0] {
+        svg.push_str(&format!(
+            "  <line x1=\"{x1}\" y1=\"{y}\" x2=\"{x2}\" y2=\"{y}\" stroke=\"#ddd\"/>\n  <text x=\"{tx}\" y=\"{ty}\" text-anchor=\"end\" fill=\"#555\">{p}%</text>\n",
+            x1 = CHART_MARGIN, x2 = CHART_WIDTH - CHART_MARGIN, y = y(*percent),
+            tx = CHART_MARGIN - 4.0, ty = y(*percent) + 3.0, p = percent));
+    }
+
+    for (idx, (branch, branch_entries)) in branches.iter().enumerate() {
+        let color = COLORS[idx % COLORS.len()];
+        let points: Vec<String> = branch_entries.iter().enumerate()
+            .map(|(i, entry)| format!("{:.1},{:.1}", x(i), y(entry.total)))
+            .collect();
+        svg.push_str(&format!("  <polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"/>\n",
+                              color, points.join(" ")));
+        if let Some(last) = branch_entries.last() {
+            svg.push_str(&format!("  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{}\"><title>{} {}: {:.2}%</title></circle>\n",
+                                  x(branch_entries.len() - 1), y(last.total), color,
+                                  escape(branch), escape(&last.commit), last.total));
+        }
+
+        // Legend, along the top
+        svg.push_str(&format!("  <text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\">{}</text>\n",
+                              CHART_MARGIN + 100.0 * idx as f64, CHART_MARGIN / 2.0, color, escape(branch)));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// A single `#[test]` function, as reported by libtest.
+#[derive(Clone, Debug)]
+pub struct TestCase {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// Only known when the binary was run with `-Z unstable-options --report-time`
+    pub duration: Option<Duration>,
+    /// Captured stdout of a failed test
+    pub output: String,
+}
+
+/// All the tests of one test binary (one target of one package).
+#[derive(Clone, Debug)]
+pub struct TestSuite {
+    pub package: String,
+    pub kind: String,
+    pub target: String,
+    pub duration: Duration,
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    fn count(&self, outcome: TestOutcome) -> usize {
+        self.cases.iter().filter(|case| case.outcome == outcome).count()
+    }
+}
+
+/// Parse the human-readable output of a libtest harness.
+///
+/// This looks for the `test NAME ... RESULT` lines, and collects the
+/// `---- NAME stdout ----` sections libtest prints for failed tests.
+pub fn parse_libtest_output(output: &str) -> Vec<TestCase> {
+    let mut cases: Vec<TestCase> = vec![];
+    let mut current_failure: Option<usize> = None;
+
+    for line in output.lines() {
+        if line.starts_with("---- ") && line.ends_with(" stdout ----") {
+            let name = &line["---- ".len()..line.len() - " stdout ----".len()];
+            current_failure = cases.iter().position(|case| case.name == name);
+            continue;
+        }
+
+        if line == "failures:" || line == "successes:" || line.starts_with("test result: ") {
+            current_failure = None;
+            continue;
+        }
+
+        if let Some(idx) = current_failure {
+            let case = &mut cases[idx];
+            case.output.push_str(line);
+            case.output.push('\n');
+            continue;
+        }
+
+        if !line.starts_with("test ") {
+            continue;
+        }
+        let (name, result) = match line["test ".len()..].find(" ... ") {
+            Some(idx) => (&line["test ".len().."test ".len() + idx], &line["test ".len() + idx + " ... ".len()..]),
+            None => continue,
+        };
+        let name = name.trim_end_matches(" - should panic");
+
+        let outcome = if result.starts_with("ok") || result.starts_with("bench:") {
+            TestOutcome::Passed
+        } else if result.starts_with("FAILED") {
+            TestOutcome::Failed
+        } else if result.starts_with("ignored") {
+            TestOutcome::Ignored
+        } else {
+            continue;
+        };
+
+        cases.push(TestCase {
+            name: name.to_string(),
+            outcome: outcome,
+            duration: parse_report_time(result),
+            output: String::new(),
+        });
+    }
+
+    cases
+}
+
+/// Parse the `<0.012s>` suffix added by `--report-time`.
+fn parse_report_time(result: &str) -> Option<Duration> {
+    let start = result.rfind('<')?;
+    let end = result.rfind("s>")?;
+    if end <= start {
+        return None;
+    }
+    let secs: f64 = result[start + 1..end].parse().ok()?;
+    Some(Duration::from_millis((secs * 1000.0) as u64))
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{}.{:03}", duration.as_secs(), duration.subsec_millis())
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 doesn't allow most control characters, even escaped
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write the given suites as a JUnit XML report.
+pub fn write_report(path: &Path, suites: &[TestSuite]) -> io::Result<()> {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+    let total_tests: usize = suites.iter().map(|suite| suite.cases.len()).sum();
+    let total_failures: usize = suites.iter().map(|suite| suite.count(TestOutcome::Failed)).sum();
+    let total_time = suites.iter().fold(Duration::new(0, 0), |acc, suite| acc + suite.duration);
+    xml.push_str(&format!("<testsuites tests=\"{}\" failures=\"{}\" time=\"{}\">\n",
+                          total_tests, total_failures, seconds(total_time)));
+
+    for suite in suites {
+        let classname = format!("{}.{}", suite.package, suite.target);
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" package=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{}\">\n",
+            escape(&format!("{} ({} {})", suite.package, suite.kind, suite.target)),
+            escape(&suite.package),
+            suite.cases.len(),
+            suite.count(TestOutcome::Failed),
+            suite.count(TestOutcome::Ignored),
+            seconds(suite.duration)));
+
+        for case in &suite.cases {
+            xml.push_str(&format!("    <testcase name=\"{}\" classname=\"{}\"", escape(&case.name), escape(&classname)));
+            if let Some(duration) = case.duration {
+                xml.push_str(&format!(" time=\"{}\"", seconds(duration)));
+            }
+            match case.outcome {
+                TestOutcome::Passed => xml.push_str("/>\n"),
+                TestOutcome::Ignored => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                TestOutcome::Failed => {
+                    let message = case.output.lines()
+                        .find(|line| line.contains("panicked at"))
+                        .unwrap_or("test failed");
+                    xml.push_str(&format!(">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                                          escape(message), escape(&case.output)));
+                }
+            }
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::File::create(path)?.write_all(xml.as_bytes())
+}
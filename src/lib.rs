@@ -1,20 +1,50 @@
 extern crate badge;
 extern crate cargo;
+extern crate chrono;
+extern crate docopt;
+extern crate env_logger;
+#[macro_use]
+extern crate failure;
 extern crate fs_extra;
+extern crate git2;
+#[macro_use]
+extern crate log;
+extern crate md5;
+extern crate semver;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
 use badge::{Badge, BadgeOptions};
-use cargo::core::Workspace;
+use cargo::core::{Package, TargetKind, Workspace};
 use cargo::ops::CompileOptions;
 use cargo::util::{config::Config, errors::ProcessError, process, CargoTestError, Test};
 use cargo::CargoResult;
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{self, Command};
+use std::time::{Duration, Instant};
+
+pub mod bench;
+pub mod ci;
+pub mod cli;
+pub mod coveralls;
+pub mod deploy;
+pub mod history;
+pub mod junit;
+pub mod links;
+pub mod report;
+pub mod summary;
+pub mod versions;
+
+use report::CoverageReport;
+use serde::Serializer;
 
 pub struct CoverageOptions<'a> {
     pub compile_opts: CompileOptions<'a>,
@@ -22,10 +52,73 @@ pub struct CoverageOptions<'a> {
     pub no_fail_fast: bool,
     pub kcov_path: &'a Path,
     pub merge_args: Vec<OsString>, // TODO: Or &[str] ?
-    pub exclude_pattern: Option<String>
+    pub exclude_pattern: Option<String>,
+    pub junit_path: Option<&'a Path>,
+}
+
+/// A test binary that was run under kcov.
+#[derive(Serialize)]
+pub struct TestBinary {
+    pub package: String,
+    pub kind: String,
+    pub name: String,
+    pub exe: PathBuf,
+    /// Where kcov put the coverage of this binary
+    pub output_dir: PathBuf,
+    pub success: bool,
+    /// `None` if the binary was killed by a signal
+    pub exit_code: Option<i32>,
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+/// Total coverage of a merged kcov report.
+#[derive(Serialize)]
+pub struct CoverageTotals {
+    pub covered_lines: u64,
+    pub total_lines: u64,
+    pub percent: f64,
+}
+
+/// The outcome of `run_coverage`.
+#[derive(Serialize)]
+pub struct CoverageRun {
+    /// The binaries that were run, in order
+    pub binaries: Vec<TestBinary>,
+    pub merge_dir: PathBuf,
+    /// `None` if the results weren't merged (because a test failed without
+    /// `no_fail_fast`), or if kcov didn't write a `coverage.json`
+    pub totals: Option<CoverageTotals>,
+    /// Set if any test failed
+    #[serde(skip)]
+    pub error: Option<CargoTestError>,
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9)
+}
+
+/// Name of a target kind, as used by cargo in its JSON messages
+fn target_kind_name(kind: &TargetKind) -> &'static str {
+    match *kind {
+        TargetKind::Lib(..) => "lib",
+        TargetKind::Bin => "bin",
+        TargetKind::Test => "test",
+        TargetKind::Bench => "bench",
+        TargetKind::ExampleLib(..) | TargetKind::ExampleBin => "example",
+        TargetKind::CustomBuild => "custom-build",
+    }
 }
 
-pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[String]) -> CargoResult<Option<CargoTestError>> {
+fn write_junit(options: &CoverageOptions, suites: &[junit::TestSuite]) -> CargoResult<()> {
+    if let Some(path) = options.junit_path {
+        try!(options.compile_opts.config.shell().status("Writing", path.display().to_string()));
+        try!(junit::write_report(path, suites));
+    }
+    Ok(())
+}
+
+pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[String]) -> CargoResult<CoverageRun> {
     // TODO: It'd be nice if there was a flag in compile_opts for this.
 
     // The compiler needs to be told to not remove any code that isn't called or
@@ -54,6 +147,13 @@ pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[Stri
     let cwd = options.compile_opts.config.cwd();
 
     let mut errors = vec![];
+    let mut suites = vec![];
+    let mut run = CoverageRun {
+        binaries: vec![],
+        merge_dir: options.merge_dir.to_path_buf(),
+        totals: None,
+        error: None,
+    };
 
     let v : Vec<std::ffi::OsString> = test_args.iter().cloned().map::<std::ffi::OsString, _>(|val| val.into()).collect();
 
@@ -75,7 +175,7 @@ pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[Stri
         let mut args = vec![
             OsString::from("--verify"),
             OsString::from(default_include_path),
-            OsString::from(target)];
+            OsString::from(target.clone())];
 
         // add exclude path
         if let Some(ref exclude) = options.exclude_pattern {
@@ -94,19 +194,73 @@ pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[Stri
             shell.status("Running", cmd.to_string())
         }));
 
-        let result = cmd.exec();
+        let started = Instant::now();
+        let result = if options.junit_path.is_some() {
+            // Capture libtest's output for the report, while still showing it
+            let mut stdout = String::new();
+            let result = cmd.exec_with_streaming(
+                &mut |line| {
+                    println!("{}", line);
+                    stdout.push_str(line);
+                    stdout.push('\n');
+                    Ok(())
+                },
+                &mut |line| {
+                    eprintln!("{}", line);
+                    Ok(())
+                },
+                false).map(|_| ());
+
+            let mut cases = junit::parse_libtest_output(&stdout);
+            if result.is_err() && !cases.iter().any(|case| case.outcome == junit::TestOutcome::Failed) {
+                // The binary died without libtest reporting a failure (crash,
+                // kcov error...), record it so it doesn't go unnoticed
+                cases.push(junit::TestCase {
+                    name: test.clone(),
+                    outcome: junit::TestOutcome::Failed,
+                    duration: None,
+                    output: stdout,
+                });
+            }
+            suites.push(junit::TestSuite {
+                package: pkg.name().to_string(),
+                kind: target_kind_name(kind).to_string(),
+                target: test.clone(),
+                duration: started.elapsed(),
+                cases: cases,
+            });
+            result
+        } else {
+            cmd.exec()
+        };
+
+        let mut binary = TestBinary {
+            package: pkg.name().to_string(),
+            kind: target_kind_name(kind).to_string(),
+            name: test.clone(),
+            exe: exe.clone(),
+            output_dir: target,
+            success: true,
+            exit_code: Some(0),
+            duration: started.elapsed(),
+        };
 
         match result {
             Err(e) => {
                 match e.downcast::<ProcessError>() {
                     Ok(e) => {
+                        binary.success = false;
+                        binary.exit_code = e.exit.and_then(|status| status.code());
+                        run.binaries.push(binary);
                         errors.push(e);
                         if !options.no_fail_fast {
-                            return Ok(Some(CargoTestError::new(Test::UnitTest {
+                            try!(write_junit(options, &suites));
+                            run.error = Some(CargoTestError::new(Test::UnitTest {
                                 kind: kind.clone(),
                                 name: test.clone(),
                                 pkg_name: pkg.name().to_string(),
-                            }, errors)))
+                            }, errors));
+                            return Ok(run)
                         }
                     }
                     Err(e) => {
@@ -115,7 +269,7 @@ pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[Stri
                     }
                 }
             }
-            Ok(()) => {}
+            Ok(()) => run.binaries.push(binary),
         }
     }
 
@@ -134,13 +288,217 @@ pub fn run_coverage(ws: &Workspace, options: &CoverageOptions, test_args: &[Stri
         shell.status("Merging coverage", cmd.to_string())
     }));
     try!(cmd.exec());
+    try!(write_junit(options, &suites));
+
+    // Older kcov versions don't write a coverage.json, that's not an error
+    if let Ok(report) = CoverageReport::load(options.merge_dir) {
+        run.totals = Some(CoverageTotals {
+            covered_lines: report.covered_lines,
+            total_lines: report.total_lines,
+            percent: report.percent(),
+        });
+    }
+
+    if !errors.is_empty() {
+        run.error = Some(CargoTestError::new(Test::Multiple, errors));
+    }
+    Ok(run)
+}
+
+pub struct BenchOptions<'a> {
+    pub compile_opts: CompileOptions<'a>,
+    pub no_fail_fast: bool,
+}
+
+/// Run the benchmarks like `cargo bench` does, and collect their results.
+///
+/// The error is set if any of the bench binaries failed.
+pub fn run_benches(ws: &Workspace, options: &BenchOptions, bench_args: &[String]) -> CargoResult<(Vec<bench::BenchResult>, Option<CargoTestError>)> {
+    let mut compilation = try!(cargo::ops::compile(ws, &options.compile_opts));
+    compilation.tests.sort_by(|a, b| {
+        (a.0.package_id(), &a.1).cmp(&(b.0.package_id(), &b.1))
+    });
+
+    let config = options.compile_opts.config;
+    let cwd = config.cwd();
+
+    let mut errors = vec![];
+    let mut results = vec![];
+
+    for &(ref pkg, ref kind, ref test, ref exe) in &compilation.tests {
+        let to_display = match cargo::util::without_prefix(exe, &cwd) {
+            Some(path) => path,
+            None => &**exe
+        };
+
+        let mut cmd = try!(compilation.target_process(exe, pkg));
+        cmd.arg("--bench");
+        cmd.args(bench_args);
+        try!(config.shell().concise(|shell| {
+            shell.status("Running", to_display.display().to_string())
+        }));
+        try!(config.shell().verbose(|shell| {
+            shell.status("Running", cmd.to_string())
+        }));
+
+        let mut stdout = String::new();
+        let result = cmd.exec_with_streaming(
+            &mut |line| {
+                println!("{}", line);
+                stdout.push_str(line);
+                stdout.push('\n');
+                Ok(())
+            },
+            &mut |line| {
+                eprintln!("{}", line);
+                Ok(())
+            },
+            false);
+
+        for mut result in try!(bench::parse_bench_output(&stdout)) {
+            result.package = pkg.name().to_string();
+            result.target = test.clone();
+            results.push(result);
+        }
+
+        if let Err(e) = result {
+            match e.downcast::<ProcessError>() {
+                Ok(e) => {
+                    errors.push(e);
+                    if !options.no_fail_fast {
+                        return Ok((results, Some(CargoTestError::new(Test::UnitTest {
+                            kind: kind.clone(),
+                            name: test.clone(),
+                            pkg_name: pkg.name().to_string(),
+                        }, errors))))
+                    }
+                }
+                Err(e) => {
+                    //This is an unexpected Cargo error rather than a bench failure
+                    return Err(e)
+                }
+            }
+        }
+    }
+
     if errors.is_empty() {
-        Ok(None)
+        Ok((results, None))
+    } else {
+        Ok((results, Some(CargoTestError::new(Test::Multiple, errors))))
+    }
+}
+
+/// Read `path` as it is on `branch` of the `origin` remote, e.g. to get a
+/// baseline stored on the deploy branch.
+pub fn read_from_branch(branch: &str, path: &str) -> CargoResult<String> {
+    try!(process("git").args(&["fetch", "--depth", "1", "origin", branch]).exec_with_output());
+    let output = try!(process("git").arg("show").arg(format!("FETCH_HEAD:{}", path.trim_start_matches('/'))).exec_with_output());
+    Ok(try!(String::from_utf8(output.stdout)))
+}
+
+/// The release channel of the toolchain in use: `stable`, `beta`, `nightly`
+/// or `dev`, as reported by `rustc -vV` (or `$RUSTC -vV`).
+pub fn rustc_channel() -> CargoResult<String> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = try!(process(&rustc).arg("-vV").exec_with_output());
+    let output = try!(String::from_utf8(output.stdout));
+
+    let release = match output.lines().find(|line| line.starts_with("release: ")) {
+        Some(line) => line["release: ".len()..].trim(),
+        None => return Err(format_err!("couldn't find the release in the output of `{} -vV`", rustc)),
+    };
+    // e.g. 1.31.0-nightly, 1.30.0-beta.15 or 1.29.2
+    let channel = match release.find('-') {
+        Some(idx) => release[idx + 1..].split('.').next().unwrap(),
+        None => "stable",
+    };
+    Ok(channel.to_string())
+}
+
+/// Write a Markdown summary of the merged coverage results in `merge_dir` to
+/// `path`, comparing it to the `baseline` report (a merge directory or a kcov
+/// `coverage.json`) if one is given.
+pub fn write_coverage_summary(ws: &Workspace, merge_dir: &Path, baseline: Option<&Path>, path: &Path) -> CargoResult<()> {
+    let report = try!(CoverageReport::load(merge_dir));
+    let packages = report.by_package(ws);
+
+    let baseline = match baseline {
+        Some(baseline) => {
+            let baseline = try!(CoverageReport::load(baseline));
+            let packages = baseline.by_package(ws);
+            Some((baseline, packages))
+        }
+        None => None,
+    };
+
+    let md = summary::markdown(&report, &packages, ws.root(),
+                               baseline.as_ref().map(|&(ref report, ref packages)| (report, &packages[..])));
+    try!(ws.config().shell().status("Writing", path.display().to_string()));
+    try!(fs::write(path, md));
+    Ok(())
+}
+
+/// Write the coveralls.io job payload for the merged coverage results in
+/// `merge_dir` to `path`, instead of sending it.
+pub fn write_coveralls_payload(ws: &Workspace, merge_dir: &Path, job_id: &str, path: &Path) -> CargoResult<()> {
+    let payload = try!(coveralls::payload(merge_dir, ws.root(), job_id));
+    try!(ws.config().shell().status("Writing", format!("coveralls payload to {}", path.display())));
+    try!(fs::write(path, payload.to_string()));
+    Ok(())
+}
+
+/// Write `badge.svg`, and `badge.json` for shields.io's endpoint badges, into
+/// `dir`.
+fn write_badge(dir: &Path, label: &str, status: &str, color: &str) -> CargoResult<()> {
+    // make badge.json
+    let json = json!({
+        "schemaVersion": 1,
+        "label": label,
+        "message": status,
+        "color": color
+    });
+
+    let mut file = try!(fs::File::create(dir.join("badge.json")));
+    try!(file.write_all(json.to_string().as_bytes()));
+
+    // make badge.svg
+    let badge_options = BadgeOptions {
+        subject: label.to_string(),
+        status: status.to_string(),
+        color: color.to_string(),
+    };
+
+    let badge = try!(Badge::new(badge_options).map_err(|err| format_err!("couldn't create badge: {}", err)));
+    let mut file = try!(fs::File::create(dir.join("badge.svg")));
+    try!(file.write_all(badge.to_svg().as_bytes()));
+    Ok(())
+}
+
+/// Badge colour for a coverage percentage, from red to bright green
+pub fn coverage_color(percent: f64) -> &'static str {
+    if percent >= 90.0 {
+        "#4c1"
+    } else if percent >= 75.0 {
+        "#97ca00"
+    } else if percent >= 60.0 {
+        "#dfb317"
+    } else if percent >= 40.0 {
+        "#fe7d37"
     } else {
-        Ok(Some(CargoTestError::new(Test::Multiple, errors)))
+        "#e05d44"
     }
 }
 
+/// Write a coverage badge for the merged results in `merge_dir` into `dir`.
+pub fn write_coverage_badge(ws: &Workspace, merge_dir: &Path, dir: &Path) -> CargoResult<()> {
+    let report = try!(CoverageReport::load(merge_dir));
+    let percent = report.percent();
+
+    try!(ws.config().shell().status("Writing", format!("coverage badge ({:.0}%) to {}", percent, dir.display())));
+    try!(fs::create_dir_all(dir));
+    write_badge(dir, "coverage", &format!("{:.0}%", percent), coverage_color(percent))
+}
+
 fn require_success(status: process::ExitStatus) {
     if !status.success() {
         process::exit(status.code().unwrap())
@@ -209,46 +567,24 @@ pub fn build_kcov<P: AsRef<Path>>(kcov_dir: P) -> PathBuf {
     kcov_built_path
 }
 
-pub fn doc_upload(message: &str, origin: &str, gh_pages: &str, doc_path: &str, local_doc_path: &Path, clobber_index: bool) -> Result<(), (String, i32)> {
-    let doc_upload = Path::new("target/doc-upload");
+/// How many times `publish` tries to push before giving up
+const PUSH_ATTEMPTS: usize = 5;
 
-    if !doc_upload.exists() {
-        // If the folder doesn't exist, clone it from remote
-        // ASSUME: if target/doc-upload exists, it's ours
-        let status = Command::new("git")
-            .arg("clone")
-            .arg("--verbose")
-            .args(&["--branch", gh_pages])
-            .args(&["--depth", "1"])
-            .arg(origin)
-            .arg(doc_upload)
-            .status()
-            .unwrap();
-        if !status.success() {
-            // If clone fails, that means that the remote doesn't exist
-            // So we create a new repository for the documentation branch
-            require_success(
-                Command::new("git")
-                    .arg("init")
-                    .arg(doc_upload)
-                    .status()
-                    .unwrap()
-            );
-            require_success(
-                Command::new("git")
-                    .current_dir(doc_upload)
-                    .arg("checkout")
-                    .args(&["-b", gh_pages])
-                    .status()
-                    .unwrap()
-            );
-        }
-    }
+/// Check out the deploy branch into `target/doc-upload`, creating a new
+/// repository for it if it doesn't exist on the remote yet.
+fn checkout_deploy_branch(origin: &deploy::Remote, gh_pages: &str, author: &deploy::Author, max_history: Option<usize>) -> Result<deploy::DeployBranch, (String, i32)> {
+    // Only what's kept of the history is needed
+    deploy::DeployBranch::checkout(origin, gh_pages, Path::new("target/doc-upload"), author, Some(max_history.unwrap_or(1)))
+        .map_err(|err| (format!("couldn't check out {}: {}", gh_pages, err), 101))
+}
 
-    let doc_upload_branch = doc_upload.join(doc_path);
+/// Create the `path` directory in the deploy branch checkout, making sure it
+/// doesn't escape it.
+fn deploy_dir(doc_upload: &Path, path: &str) -> Result<PathBuf, (String, i32)> {
+    let doc_upload_branch = doc_upload.join(path);
 
     println!("mkdir {}", doc_upload_branch.display());
-    let res = fs::create_dir(&doc_upload_branch);
+    let res = fs::create_dir_all(&doc_upload_branch);
 
     match res.as_ref().map_err(|err| err.kind()) {
         Err(std::io::ErrorKind::AlreadyExists) | Ok(()) => (),
@@ -258,137 +594,556 @@ pub fn doc_upload(message: &str, origin: &str, gh_pages: &str, doc_path: &str, l
     // we can't canonicalize before we create the folder
     let doc_upload_branch = doc_upload_branch.canonicalize().unwrap();
 
-    if !doc_upload_branch.starts_with(env::current_dir().unwrap().join(doc_upload)) {
+    if !doc_upload_branch.starts_with(doc_upload.canonicalize().unwrap()) {
         return Err(("Path passed in `--path` is outside the intended `target/doc-upload` folder".to_string(), 1));
     }
+    Ok(doc_upload_branch)
+}
 
-    for entry in doc_upload_branch.read_dir().unwrap() {
-        let dir = entry.unwrap();
-        // Delete all files in directory, as we'll be copying in everything
-        // Ignore index.html (at root) so a redirect page can be manually added
-        // Unless user wants otherwise (--clobber-index)
-        // Or a new one was generated
-        if dir.file_name() != OsString::from("index.html")
-            || clobber_index
-            || local_doc_path.join("index.html").exists()
-        {
-            let path = dir.path();
-            println!("rm -r {}", path.to_string_lossy());
-            fs::remove_dir_all(&path).ok();
-            fs::remove_file(path).ok();
+/// Whether `dir` is a coverage report published by `coverage_upload`
+fn is_coverage_report(dir: &Path) -> bool {
+    dir.join("kcov-merged").is_dir()
+}
+
+/// Copy the content of `from` into `to`, reporting progress as we go.
+fn copy_dir_contents(from: fs::ReadDir, to: &Path) {
+    let mut last_progress = 0;
+    fs_extra::copy_items_with_progress(
+        &from.map(|entry| entry.unwrap().path()).collect(),
+        to,
+        &fs_extra::dir::CopyOptions::new(),
+        |info| {
+            // Some documentation can be very large, especially with a large number of dependencies
+            // Don't go silent during copy, give updates every MiB processed
+            if info.copied_bytes >> 20 > last_progress {
+                last_progress = info.copied_bytes >> 20;
+                println!("{}/{} MiB", info.copied_bytes >> 20, info.total_bytes >> 20);
+            }
+            fs_extra::dir::TransitProcessResult::ContinueOrAbort
+        }
+    ).unwrap();
+}
+
+/// How many files `sync_dir` wrote, removed or left as is.
+#[derive(Default)]
+struct SyncStats {
+    added: usize,
+    changed: usize,
+    removed: usize,
+    unchanged: usize,
+}
+
+/// Make `to` a copy of `from`, only writing the files whose content changed
+/// and removing the ones `from` doesn't have, except for the entries at the
+/// root of `to` for which `keep` returns true.
+///
+/// A missing `from` is taken as an empty directory.
+fn sync_dir<F>(from: &Path, to: &Path, keep: F) -> std::io::Result<SyncStats>
+    where F: Fn(&Path) -> bool
+{
+    let mut stats = SyncStats::default();
+    if from.is_dir() {
+        copy_changed(from, to, &mut stats)?;
+    }
+    for entry in to.read_dir()? {
+        let entry = entry?;
+        if !keep(&entry.path()) {
+            remove_vanished(&from.join(entry.file_name()), &entry, &mut stats)?;
         }
     }
+    Ok(stats)
+}
 
-    // default badge shows that no successful build was made
-    let mut badge_status = "no builds".to_string();
-    let mut badge_color = "#e05d44".to_string();
+/// Copy the files of `from` to `to` whose content differs.
+fn copy_changed(from: &Path, to: &Path, stats: &mut SyncStats) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            if target.is_file() {
+                fs::remove_file(&target)?;
+            }
+            copy_changed(&entry.path(), &target, stats)?;
+            continue;
+        }
 
-    // try to read manifest to extract version number
-    let config = Config::default().expect("failed to create cargo Config");
-    let mut version = Err(());
+        let contents = fs::read(entry.path())?;
+        if target.is_dir() {
+            fs::remove_dir_all(&target)?;
+        }
+        match fs::metadata(&target) {
+            // Only read the deployed file when it may be the same
+            Ok(ref metadata) if metadata.len() == contents.len() as u64 && fs::read(&target)? == contents => {
+                stats.unchanged += 1;
+            }
+            Ok(_) => {
+                fs::write(&target, contents)?;
+                stats.changed += 1;
+            }
+            Err(_) => {
+                fs::write(&target, contents)?;
+                stats.added += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove `entry`, or what's in it, if `source` doesn't have it.
+fn remove_vanished(source: &Path, entry: &fs::DirEntry, stats: &mut SyncStats) -> std::io::Result<()> {
+    if entry.file_type()?.is_dir() {
+        for child in entry.path().read_dir()? {
+            let child = child?;
+            remove_vanished(&source.join(child.file_name()), &child, stats)?;
+        }
+        if !source.is_dir() {
+            fs::remove_dir(entry.path())?;
+        }
+    } else if !source.is_file() {
+        fs::remove_file(entry.path())?;
+        stats.removed += 1;
+    }
+    Ok(())
+}
 
-    let mut manifest = env::current_dir().unwrap();
-    manifest.push("Cargo.toml");
+/// Sync the documentation in `from` into `to`, see `sync_dir`, and report
+/// what changed.
+fn sync_docs<F>(from: &Path, to: &Path, keep: F) -> Result<(), (String, i32)>
+    where F: Fn(&Path) -> bool
+{
+    println!("sync {} {}", from.to_string_lossy(), to.to_string_lossy());
+    let stats = sync_dir(from, to, keep)
+        .map_err(|err| (format!("couldn't copy {} to {}: {}", from.display(), to.display(), err), 101))?;
+    println!("{} files added, {} changed, {} removed, {} unchanged",
+             stats.added, stats.changed, stats.removed, stats.unchanged);
+    Ok(())
+}
 
-    match Workspace::new(&manifest, &config) {
-        Ok(workspace) => match workspace.current() {
-            Ok(package) => version = Ok(format!("{}", package.manifest().version())),
-            Err(error) => println!("couldn't get package: {}", error),
-        },
-        Err(error) => println!("couldn't generate workspace: {}", error),
+/// Apply `update` to the deploy branch checkout, commit the result, and push
+/// it.
+///
+/// If the push is rejected because another build pushed in the meantime, the
+/// deploy branch is fetched again and `update` applied on top of it, up to
+/// `PUSH_ATTEMPTS` times. With `dry_run`, the commit is made but only
+/// summarized instead of pushed.
+///
+/// With `max_history`, only that many commits are kept in the history of the
+/// deploy branch, which is then force-pushed.
+fn publish<F>(deploy: &deploy::DeployBranch, message: &str, gh_pages: &str, max_history: Option<usize>, dry_run: bool, mut update: F) -> Result<(), (String, i32)>
+    where F: FnMut(&Path) -> Result<(), (String, i32)>
+{
+    for attempt in 1..PUSH_ATTEMPTS + 1 {
+        update(deploy.path())?;
+
+        let commit = deploy.commit(message)
+            .map_err(|err| (format!("couldn't commit to {}: {}", gh_pages, err), 101))?;
+        match commit {
+            None => {
+                println!("No changes to the documentation.");
+                return Ok(());
+            }
+            Some(mut commit) => {
+                if let Some(max_history) = max_history {
+                    commit = deploy.truncate_history(max_history)
+                        .map_err(|err| (format!("couldn't rewrite the history of {}: {}", gh_pages, err), 101))?;
+                }
+                if dry_run {
+                    deploy.show(commit).map_err(|err| (err.to_string(), 101))?;
+                    println!("Dry run, not pushing to {}.", gh_pages);
+                    return Ok(());
+                }
+            }
+        }
+
+        if deploy.push(max_history.is_some()).map_err(|err| (err.to_string(), 101))? {
+            return Ok(());
+        }
+        println!("{} was updated by someone else in the meantime, retrying on top of it ({}/{})",
+                 gh_pages, attempt, PUSH_ATTEMPTS);
+        deploy.fetch().map_err(|err| (format!("couldn't fetch {}: {}", gh_pages, err), 101))?;
     }
+    Err((format!("couldn't push to {}, it kept being updated by someone else", gh_pages), 101))
+}
+
+/// Publish the documentation in `local_doc_path` of `branch` to `doc_path` on
+/// the deploy branch.
+///
+/// For the build of `tag`, the documentation is also copied to `latest` if
+/// the manifest's version is the highest one published. The version and the
+/// crates the landing page leads to are the ones of `ws`, if it was loaded.
+///
+/// With `prune`, the documentation of deleted branches is removed in the same
+/// commit, see `PruneOptions`.
+pub fn doc_upload(message: &str, author: &deploy::Author, origin: &deploy::Remote, gh_pages: &str, doc_path: &str, branch: Option<&str>, tag: Option<&str>, ws: Option<&Workspace>, local_doc_path: &Path, clobber_index: bool, prune: Option<&PruneOptions>, max_history: Option<usize>, dry_run: bool) -> Result<(), (String, i32)> {
+    // default badge shows that no successful build was made
+    let mut badge_status = "no builds".to_string();
+    let mut badge_color = "#e05d44".to_string();
+
+    let package = ws.and_then(main_package);
+    let version = package.map(|package| package.version().to_string()).ok_or(());
+    let name = package.map(|package| package.name().to_string());
+    let landing = ws.map(landing_crates).unwrap_or((String::new(), vec![]));
 
     // update badge to contain version number
     if let Ok(version) = &version {
         badge_status = version.clone();
     }
 
-    let doc = local_doc_path;
-    println!("cp {} {}", doc.to_string_lossy(), doc_upload_branch.to_string_lossy());
-    let mut last_progress = 0;
-
     let mut result = Ok(());
 
-    if let Ok(doc) = doc.read_dir() {
-        fs_extra::copy_items_with_progress(
-            &doc.map(|entry| entry.unwrap().path()).collect(),
-            &doc_upload_branch,
-            &fs_extra::dir::CopyOptions::new(),
-            |info| {
-                // Some documentation can be very large, especially with a large number of dependencies
-                // Don't go silent during copy, give updates every MiB processed
-                if info.copied_bytes >> 20 > last_progress {
-                    last_progress = info.copied_bytes >> 20;
-                    println!("{}/{} MiB", info.copied_bytes >> 20, info.total_bytes >> 20);
-                }
-                fs_extra::dir::TransitProcessResult::ContinueOrAbort
-            }
-        ).unwrap();
-
+    if local_doc_path.read_dir().is_ok() {
         // update the badge to reflect build was successful
         // but only if we managed to extract a version number
         if version.is_ok() {
             badge_color = "#4d76ae".to_string();
         }
-    }
-    else {
+    } else {
         println!("No documentation found to upload.");
         result = Err(("No documentation generated".to_string(), 1));
     }
-    
-    // make badge.json
-    let json = json!({
-        "schemaVersion": 1,
-        "label": "docs",
-        "message": badge_status,
-        "color": badge_color
-    });
 
-    let mut file = fs::File::create(doc_upload_branch.join("badge.json")).unwrap();
-    file.write_all(json.to_string().as_bytes()).unwrap();
+    let deploy = checkout_deploy_branch(origin, gh_pages, author, max_history)?;
+    publish(&deploy, message, gh_pages, max_history, dry_run, |doc_upload| {
+        let doc_upload_branch = deploy_dir(doc_upload, doc_path)?;
 
-    // make badge.svg
-    let badge_options = BadgeOptions {
-        subject: "docs".to_string(),
-        status: badge_status.to_string(),
-        color: badge_color.to_string(),
+        // Remove the files the new documentation doesn't have, but
+        // Ignore index.html (at root) so a redirect page can be manually added
+        // Unless user wants otherwise (--clobber-index)
+        // Or a new one was generated
+        // Coverage reports are managed by coverage_upload, leave them be
+        // The badge is written again below
+        sync_docs(local_doc_path, &doc_upload_branch, |path| {
+            let file_name = path.file_name().unwrap();
+            is_coverage_report(path)
+                || file_name == "badge.svg" || file_name == "badge.json"
+                || (file_name == "index.html" && !clobber_index && !local_doc_path.join("index.html").exists())
+        })?;
+
+        write_badge(&doc_upload_branch, "docs", &badge_status, &badge_color).unwrap();
+        write_landing_page(&doc_upload_branch, &landing.0, &landing.1);
+
+        if result.is_ok() {
+            let entries = update_versions(doc_upload, doc_path, branch, tag, &doc_upload_branch, name.clone(), version.clone().ok())?;
+            let latest = versions::latest_release(&entries).map(|entry| entry.path.clone());
+            if tag.is_some() && latest.as_ref().map(|path| &path[..]) == Some(doc_path.trim_matches('/')) {
+                let latest_dir = deploy_dir(doc_upload, "latest")?;
+                sync_docs(local_doc_path, &latest_dir, |path| {
+                    let file_name = path.file_name().unwrap();
+                    file_name == "badge.svg" || file_name == "badge.json"
+                        || (file_name == "index.html" && versions::is_generated_index(path))
+                })?;
+                write_badge(&latest_dir, "docs", &badge_status, &badge_color).unwrap();
+                write_landing_page(&latest_dir, &landing.0, &landing.1);
+                update_versions(doc_upload, "latest", None, None, &latest_dir, name.clone(), version.clone().ok())?;
+            }
+        }
+
+        if let Some(prune) = prune {
+            prune_docs(&deploy, doc_path, prune, name.clone())?;
+        }
+        Ok(())
+    })?;
+    result
+}
+
+/// The package `ws` is about: the current one, or the only default member of
+/// a virtual manifest.
+pub fn main_package<'a>(ws: &'a Workspace) -> Option<&'a Package> {
+    if let Ok(package) = ws.current() {
+        return Some(package);
+    }
+    let default_members: Vec<_> = ws.default_members().collect();
+    if default_members.len() == 1 {
+        Some(default_members[0])
+    } else {
+        None
+    }
+}
+
+/// The crates the `index.html` of the documentation of `ws` should lead to,
+/// as their package name and the path of their documentation, along with the
+/// title of the page listing them.
+///
+/// That's the current package, or the only default member of a virtual
+/// manifest, if any, and all its members otherwise.
+fn landing_crates(ws: &Workspace) -> (String, Vec<(String, String)>) {
+    let title = ws.root().file_name().map_or("Documentation".to_string(), |name| name.to_string_lossy().into_owned());
+    let packages = match main_package(ws) {
+        Some(package) => vec![package],
+        None => ws.members().collect(),
     };
 
-    let mut file = fs::File::create(doc_upload_branch.join("badge.svg")).unwrap();
-    file.write_all(Badge::new(badge_options).unwrap().to_svg().as_bytes()).unwrap();
+    let crates = packages.iter()
+        .filter_map(|package| {
+            // rustdoc documents the library, or the binaries of packages
+            // without one
+            let target = package.targets().iter().find(|target| target.is_lib())
+                .or_else(|| package.targets().iter().find(|target| target.is_bin()));
+            target.map(|target| (package.name().to_string(), format!("{}/index.html", target.crate_name())))
+        })
+        .collect();
+    (title, crates)
+}
 
-    // Tell git to track all of the files we copied over
-    // Also tracks deletions of files if things changed
-    require_success(
-        Command::new("git")
-            .current_dir(doc_upload)
-            .arg("add")
-            .arg("--verbose")
-            .arg("--all")
-            .status()
-            .unwrap()
-    );
+/// Write an `index.html` into `dir` leading to the documentation of `crates`
+/// found there, unless rustdoc wrote one or a hand-written one was kept.
+///
+/// It redirects to the documentation of a single crate, or lists them.
+fn write_landing_page(dir: &Path, title: &str, crates: &[(String, String)]) {
+    let index_path = dir.join("index.html");
+    if !versions::is_generated_index(&index_path) {
+        return;
+    }
+
+    let documented: Vec<_> = crates.iter().filter(|&&(_, ref link)| dir.join(link).exists()).cloned().collect();
+    let html = match documented.len() {
+        0 => {
+            fs::remove_file(&index_path).ok();
+            return;
+        }
+        1 => versions::redirect_html(&documented[0].1),
+        _ => versions::members_html(title, &documented),
+    };
+    println!("Writing {}", index_path.display());
+    fs::write(&index_path, html).unwrap();
+}
+
+/// Record the documentation published to `doc_path` in `versions.json` at the
+/// root of the deploy branch, and list them all in the root `index.html`,
+/// unless it was written by hand.
+///
+/// Returns all the recorded entries.
+fn update_versions(doc_upload: &Path, doc_path: &str, branch: Option<&str>, tag: Option<&str>, doc_upload_branch: &Path, name: Option<String>, version: Option<String>) -> Result<Vec<versions::VersionEntry>, (String, i32)> {
+    let doc_path = doc_path.trim_matches('/');
+
+    // Link to the crate's documentation rather than to the directory listing
+    // GitHub pages doesn't have
+    let link = if doc_upload_branch.join("index.html").exists() {
+        format!("{}/index.html", doc_path)
+    } else {
+        match name.as_ref().map(|name| name.replace('-', "_")) {
+            Some(ref krate) if doc_upload_branch.join(krate).join("index.html").exists() => format!("{}/{}/index.html", doc_path, krate),
+            _ => format!("{}/", doc_path),
+        }
+    };
+
+    let versions_path = doc_upload.join("versions.json");
+    let mut entries = versions::load(&versions_path)
+        .map_err(|err| (format!("couldn't read {}: {}", versions_path.display(), err), 1))?;
+    versions::update(&mut entries, versions::VersionEntry {
+        path: doc_path.to_string(),
+        link: link,
+        version: version,
+        updated: chrono::Utc::now().to_rfc3339(),
+        commit: current_commit(),
+        branch: branch.map(|branch| branch.to_string()),
+        tag: tag.map(|tag| tag.to_string()),
+    });
+    println!("Recording {} in {}", doc_path, versions_path.display());
+    save_versions(doc_upload, &entries, name);
+    Ok(entries)
+}
+
+/// Write `versions.json` and the root `index.html` listing `entries`.
+fn save_versions(doc_upload: &Path, entries: &[versions::VersionEntry], name: Option<String>) {
+    versions::save(&doc_upload.join("versions.json"), entries).unwrap();
 
-    // Save the changes
-    if Command::new("git")
-        .current_dir(doc_upload)
-        .arg("commit")
-        .arg("--verbose")
-        .args(&["-m", message])
-        .status().is_err()
-    {
-        println!("No changes to the documentation.");
+    let index_path = doc_upload.join("index.html");
+    if versions::is_generated_index(&index_path) {
+        let title = name.unwrap_or_else(|| "Documentation".to_string());
+        fs::write(&index_path, versions::index_html(&title, entries)).unwrap();
     } else {
-        // Push changes to GitHub
-        require_success(
-            Command::new("git")
-                .current_dir(doc_upload)
-                .arg("push")
-                .arg(origin)
-                .arg(gh_pages)
-                .status()
-                .unwrap(),
-        );
+        println!("Leaving the hand-written {} as is", index_path.display());
     }
-    result
+}
+
+/// Which of the published documentation directories `doc_upload --prune`
+/// keeps, besides the ones of existing branches and tags.
+pub struct PruneOptions<'a> {
+    /// Keep the documentation of all tags, even deleted ones
+    pub keep_tags: bool,
+    /// Also remove the documentation not updated for this many days
+    pub max_age: Option<u64>,
+    /// Directories never to remove
+    pub protected: &'a [String],
+    /// Also consider the directories whose branch wasn't recorded, assuming
+    /// they are named after it
+    pub unknown: bool,
+}
+
+/// Remove the documentation directories of the deploy branch whose branch or
+/// tag doesn't exist on the remote anymore, or which are older than
+/// `options.max_age`.
+///
+/// Only directories written by `doc_upload` (recorded in `versions.json` or
+/// with a badge) are considered, at any depth as `feature/foo` is published
+/// to `feature/foo/`, and only if the branch or tag they were published from
+/// is recorded, see `PruneOptions::unknown`. `latest`, the documentation being
+/// uploaded and `options.protected` are always kept.
+fn prune_docs(deploy: &deploy::DeployBranch, doc_path: &str, options: &PruneOptions, name: Option<String>) -> Result<(), (String, i32)> {
+    let doc_upload = deploy.path();
+    let refs = deploy.remote_refs().map_err(|err| (format!("couldn't list the branches and tags of the remote: {}", err), 101))?;
+    let versions_path = doc_upload.join("versions.json");
+    let mut entries = versions::load(&versions_path)
+        .map_err(|err| (format!("couldn't read {}: {}", versions_path.display(), err), 1))?;
+    let now = chrono::Utc::now();
+
+    let mut paths: Vec<String> = entries.iter().map(|entry| entry.path.clone()).collect();
+    find_badge_dirs(doc_upload, "", &mut paths);
+    paths.sort();
+    paths.dedup();
+
+    let mut pruned = vec![];
+    for path in paths {
+        let dir = doc_upload.join(&path);
+        if !dir.is_dir() || path.split('/').any(|component| component.starts_with('.')) || path == "latest"
+            || path == doc_path.trim_matches('/') || options.protected.contains(&path) {
+            continue;
+        }
+
+        // Releases are published under their version rather than their tag,
+        // and branches wherever --path says
+        let recorded = entries.iter().find(|entry| entry.path == path);
+        let source = match recorded {
+            Some(&versions::VersionEntry { tag: Some(_), .. }) if options.keep_tags => continue,
+            Some(&versions::VersionEntry { tag: Some(ref tag), .. }) => tag,
+            Some(&versions::VersionEntry { branch: Some(ref branch), .. }) => branch,
+            // Uploaded before the branch was recorded
+            _ if options.unknown => &path,
+            _ => continue,
+        };
+        let stale = !refs.contains(source);
+
+        let expired = match (options.max_age, recorded) {
+            (Some(max_age), Some(entry)) => chrono::DateTime::parse_from_rfc3339(&entry.updated)
+                .map(|updated| now.signed_duration_since(updated) > chrono::Duration::days(max_age as i64))
+                .unwrap_or(false),
+            _ => false,
+        };
+
+        if stale || expired {
+            println!("rm -r {} ({})", dir.display(), if stale { "deleted from the remote" } else { "too old" });
+            fs::remove_dir_all(&dir).unwrap();
+            // Leave no empty `feature/` behind
+            let mut parent = dir.parent();
+            while let Some(empty) = parent.filter(|parent| *parent != doc_upload && fs::remove_dir(parent).is_ok()) {
+                parent = empty.parent();
+            }
+            pruned.push(path);
+        }
+    }
+
+    if !pruned.is_empty() {
+        entries.retain(|entry| !pruned.contains(&entry.path));
+        save_versions(doc_upload, &entries, name);
+    }
+    Ok(())
+}
+
+/// Add the paths, relative to the root of the deploy branch, of the
+/// directories with a documentation badge found in `dir`, which is at
+/// `prefix`.
+///
+/// The documentation itself and coverage reports aren't searched.
+fn find_badge_dirs(dir: &Path, prefix: &str, paths: &mut Vec<String>) {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        let entry = entry.unwrap();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if !path.is_dir() || name.starts_with('.') || is_coverage_report(&path) {
+            continue;
+        }
+        let relative = format!("{}{}", prefix, name);
+        if path.join("badge.svg").exists() {
+            paths.push(relative);
+        } else {
+            find_badge_dirs(&path, &format!("{}/", relative), paths);
+        }
+    }
+}
+
+/// Commit currently being built, according to Travis or to git
+fn current_commit() -> String {
+    if let Ok(commit) = env::var("TRAVIS_COMMIT") {
+        return commit;
+    }
+    Command::new("git")
+        .args(&["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Build the coverage history entry of the merged kcov report in `merge_dir`.
+fn coverage_history_entry(merge_dir: &Path, branch: &str) -> CargoResult<history::HistoryEntry> {
+    let report = try!(CoverageReport::load(merge_dir));
+
+    let mut packages = BTreeMap::new();
+    let config = try!(Config::default());
+    let manifest = env::current_dir().unwrap().join("Cargo.toml");
+    match Workspace::new(&manifest, &config) {
+        Ok(workspace) => {
+            for package in report.by_package(&workspace) {
+                packages.insert(package.name.clone(), package.percent());
+            }
+        }
+        Err(error) => println!("couldn't generate workspace: {}", error),
+    }
+
+    Ok(history::HistoryEntry {
+        commit: current_commit(),
+        date: chrono::Utc::now().to_rfc3339(),
+        branch: branch.to_string(),
+        total: report.percent(),
+        packages: packages,
+    })
+}
+
+/// Publish the merged kcov report in `merge_dir` to `path` on the deploy
+/// branch, replacing the previous report.
+///
+/// The totals are also appended to `coverage-history.json` at the root of the
+/// deploy branch, and charted in `coverage-history.svg`.
+pub fn coverage_upload(message: &str, author: &deploy::Author, origin: &deploy::Remote, gh_pages: &str, branch: &str, path: &str, merge_dir: &Path, max_history: Option<usize>, dry_run: bool) -> Result<(), (String, i32)> {
+    if merge_dir.read_dir().is_err() {
+        return Err((format!("No coverage report found in {}", merge_dir.display()), 1));
+    }
+
+    let history_entry = coverage_history_entry(merge_dir, branch);
+    if let Err(ref error) = history_entry {
+        println!("couldn't read the coverage totals, history not updated: {}", error);
+    }
+
+    let deploy = checkout_deploy_branch(origin, gh_pages, author, max_history)?;
+    publish(&deploy, message, gh_pages, max_history, dry_run, |doc_upload| {
+        let doc_upload_branch = deploy_dir(doc_upload, path)?;
+
+        for entry in doc_upload_branch.read_dir().unwrap() {
+            let path = entry.unwrap().path();
+            println!("rm -r {}", path.to_string_lossy());
+            fs::remove_dir_all(&path).ok();
+            fs::remove_file(path).ok();
+        }
+
+        println!("cp {} {}", merge_dir.to_string_lossy(), doc_upload_branch.to_string_lossy());
+        copy_dir_contents(merge_dir.read_dir().unwrap(), &doc_upload_branch);
+
+        if let Ok(ref entry) = history_entry {
+            let history_path = doc_upload.join("coverage-history.json");
+            let mut entries = history::load(&history_path)
+                .map_err(|err| (format!("couldn't read {}: {}", history_path.display(), err), 1))?;
+            println!("Recording {:.2}% coverage for {} in {}", entry.total, branch, history_path.display());
+            entries.push(entry.clone());
+            history::save(&history_path, &entries).unwrap();
+            fs::write(doc_upload.join("coverage-history.svg"), history::trend_svg(&entries)).unwrap();
+        }
+        Ok(())
+    })
 }
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// A relative link of a page which doesn't lead anywhere.
+#[derive(Clone, Debug)]
+pub struct BrokenLink {
+    /// The page with the link, relative to the checked directory
+    pub page: PathBuf,
+    pub href: String,
+    pub reason: &'static str,
+}
+
+/// Check the relative links and anchors of all the HTML pages in `dir`, e.g.
+/// the documentation written by rustdoc.
+///
+/// Links to other sites are not followed, and links leaving `dir` are broken,
+/// as they won't lead anywhere once uploaded. The `index.html` at the root of
+/// `dir`, which rustdoc's pages link to, is assumed to exist as `doc_upload`
+/// writes one if rustdoc doesn't.
+///
+/// Every broken link is reported once per page.
+pub fn check(dir: &Path) -> io::Result<Vec<BrokenLink>> {
+    let mut pages = vec![];
+    find_pages(dir, &mut pages)?;
+    pages.sort();
+
+    let mut anchors = BTreeMap::new();
+    let mut broken = vec![];
+    for page in &pages {
+        let html = fs::read_to_string(page)?;
+        let hrefs: BTreeSet<_> = attributes(&html, "href").into_iter().collect();
+        for href in hrefs {
+            if let Some(reason) = check_link(dir, page, &href, &mut anchors)? {
+                broken.push(BrokenLink {
+                    page: page.strip_prefix(dir).unwrap_or(page).to_path_buf(),
+                    href: href,
+                    reason: reason,
+                });
+            }
+        }
+    }
+    Ok(broken)
+}
+
+fn find_pages(dir: &Path, pages: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_pages(&path, pages)?;
+        } else if path.extension().map_or(false, |extension| extension == "html") {
+            pages.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Why the link `href` of `page` is broken, if it is.
+fn check_link(dir: &Path, page: &Path, href: &str, anchors: &mut BTreeMap<PathBuf, BTreeSet<String>>)
+              -> io::Result<Option<&'static str>> {
+    if href.is_empty() || href.starts_with("//") || href.starts_with('/') || has_scheme(href) {
+        return Ok(None);
+    }
+
+    let href = href.split('?').next().unwrap();
+    let (path, anchor) = match href.find('#') {
+        Some(hash) => (&href[..hash], Some(&href[hash + 1..])),
+        None => (href, None),
+    };
+
+    let target = if path.is_empty() {
+        page.to_path_buf()
+    } else {
+        match resolve(dir, page.parent().unwrap(), path) {
+            Some(target) => target,
+            None => return Ok(Some("leaves the documentation")),
+        }
+    };
+    let target = if target.is_dir() { target.join("index.html") } else { target };
+    if target == dir.join("index.html") {
+        return Ok(None);
+    }
+    if !target.is_file() {
+        return Ok(Some("missing file"));
+    }
+
+    let anchor = match anchor {
+        // rustdoc's source pages highlight lines and ranges of lines with
+        // anchors its scripts handle
+        Some(anchor) if !anchor.is_empty() && !is_line_anchor(anchor) => anchor,
+        _ => return Ok(None),
+    };
+    if target.extension().map_or(true, |extension| extension != "html") {
+        return Ok(None);
+    }
+    if !anchors.contains_key(&target) {
+        let html = fs::read_to_string(&target)?;
+        let ids = attributes(&html, "id").into_iter().chain(attributes(&html, "name")).collect();
+        anchors.insert(target.clone(), ids);
+    }
+    if anchors[&target].contains(anchor) {
+        Ok(None)
+    } else {
+        Ok(Some("missing anchor"))
+    }
+}
+
+/// Whether `href` starts with a URL scheme, e.g. `https:` or `mailto:`
+fn has_scheme(href: &str) -> bool {
+    match href.find(':') {
+        Some(colon) => href[..colon].chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'),
+        None => false,
+    }
+}
+
+fn is_line_anchor(anchor: &str) -> bool {
+    !anchor.is_empty() && anchor.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+/// Join the relative `path` to `base`, or `None` if it goes above `dir`.
+fn resolve(dir: &Path, base: &Path, path: &str) -> Option<PathBuf> {
+    let mut target = base.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::ParentDir => {
+                if !target.pop() || !target.starts_with(dir) {
+                    return None;
+                }
+            }
+            Component::Normal(name) => target.push(name),
+            _ => {}
+        }
+    }
+    Some(target)
+}
+
+/// The values of the `name` attributes in `html`, with the usual entities
+/// decoded.
+fn attributes(html: &str, name: &str) -> Vec<String> {
+    let mut values = vec![];
+    let pattern = format!(" {}=", name);
+    let mut rest = html;
+    while let Some(start) = rest.find(&pattern[..]) {
+        rest = &rest[start + pattern.len()..];
+        let (quote, value_start) = match rest.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => (quote, 1),
+            _ => continue,
+        };
+        if let Some(end) = rest[value_start..].find(quote) {
+            values.push(unescape(&rest[value_start..value_start + end]));
+            rest = &rest[value_start + end..];
+        }
+    }
+    values
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&")
+}
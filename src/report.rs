@@ -0,0 +1,130 @@
+use cargo::core::Workspace;
+use cargo::CargoResult;
+use serde_json::{self, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Coverage of a single source file, as reported by kcov.
+#[derive(Clone, Debug)]
+pub struct FileCoverage {
+    pub path: PathBuf,
+    pub covered_lines: u64,
+    pub total_lines: u64,
+}
+
+/// Coverage of a set of source files, e.g. all the files of a package.
+#[derive(Clone, Debug)]
+pub struct PackageCoverage {
+    pub name: String,
+    pub covered_lines: u64,
+    pub total_lines: u64,
+}
+
+/// The merged results of a kcov run, read from its `coverage.json`.
+#[derive(Clone, Debug)]
+pub struct CoverageReport {
+    pub files: Vec<FileCoverage>,
+    pub covered_lines: u64,
+    pub total_lines: u64,
+}
+
+pub fn percent(covered_lines: u64, total_lines: u64) -> f64 {
+    if total_lines == 0 {
+        0.0
+    } else {
+        covered_lines as f64 * 100.0 / total_lines as f64
+    }
+}
+
+impl FileCoverage {
+    pub fn percent(&self) -> f64 {
+        percent(self.covered_lines, self.total_lines)
+    }
+}
+
+impl PackageCoverage {
+    pub fn percent(&self) -> f64 {
+        percent(self.covered_lines, self.total_lines)
+    }
+}
+
+// kcov writes most numbers in coverage.json as strings
+fn as_u64(value: &Value) -> Option<u64> {
+    match *value {
+        Value::Number(ref n) => n.as_u64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+impl CoverageReport {
+    /// Load the report of a merged kcov directory (as passed to
+    /// `--merge-into`), or directly from a `coverage.json` file.
+    pub fn load(path: &Path) -> CargoResult<CoverageReport> {
+        let path = if path.is_dir() {
+            path.join("kcov-merged").join("coverage.json")
+        } else {
+            path.to_path_buf()
+        };
+        let contents = fs::read_to_string(&path)
+            .map_err(|err| format_err!("couldn't read coverage report {}: {}", path.display(), err))?;
+        CoverageReport::from_json(&contents)
+            .map_err(|err| format_err!("couldn't parse coverage report {}: {}", path.display(), err))
+    }
+
+    pub fn from_json(json: &str) -> CargoResult<CoverageReport> {
+        let json: Value = serde_json::from_str(json)?;
+
+        let mut files = vec![];
+        for file in json["files"].as_array().map(|files| &files[..]).unwrap_or(&[]) {
+            files.push(FileCoverage {
+                path: PathBuf::from(file["file"].as_str().ok_or_else(|| format_err!("file without a path"))?),
+                covered_lines: as_u64(&file["covered_lines"]).unwrap_or(0),
+                total_lines: as_u64(&file["total_lines"]).unwrap_or(0),
+            });
+        }
+
+        // Older kcov versions don't have the totals, compute them ourselves
+        let covered_lines = as_u64(&json["covered_lines"])
+            .unwrap_or_else(|| files.iter().map(|file| file.covered_lines).sum());
+        let total_lines = as_u64(&json["total_lines"])
+            .unwrap_or_else(|| files.iter().map(|file| file.total_lines).sum());
+
+        Ok(CoverageReport {
+            files: files,
+            covered_lines: covered_lines,
+            total_lines: total_lines,
+        })
+    }
+
+    pub fn percent(&self) -> f64 {
+        percent(self.covered_lines, self.total_lines)
+    }
+
+    /// Group the files by the workspace member they belong to. Files outside
+    /// of every member are ignored.
+    pub fn by_package(&self, ws: &Workspace) -> Vec<PackageCoverage> {
+        let mut packages: Vec<(&Path, PackageCoverage)> = ws.members()
+            .map(|pkg| (pkg.root(), PackageCoverage {
+                name: pkg.name().to_string(),
+                covered_lines: 0,
+                total_lines: 0,
+            }))
+            .collect();
+
+        for file in &self.files {
+            // Members can be nested in one another, the deepest root wins
+            let owner = packages.iter_mut()
+                .filter(|&&mut (root, _)| file.path.starts_with(root))
+                .max_by_key(|&&mut (root, _)| root.components().count());
+            if let Some(&mut (_, ref mut package)) = owner {
+                package.covered_lines += file.covered_lines;
+                package.total_lines += file.total_lines;
+            }
+        }
+
+        let mut packages: Vec<PackageCoverage> = packages.into_iter().map(|(_, package)| package).collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        packages
+    }
+}
@@ -0,0 +1,64 @@
+use report::{CoverageReport, PackageCoverage};
+use std::path::Path;
+
+/// How many of the least covered files are listed in the summary
+const LOWEST_FILES: usize = 5;
+
+fn change(current: f64, baseline: Option<f64>) -> String {
+    match baseline {
+        Some(baseline) => format!("{:+.2}%", current - baseline),
+        None => "new".to_string(),
+    }
+}
+
+/// Render a Markdown summary of the coverage, suitable for a pull request
+/// comment or `$GITHUB_STEP_SUMMARY`.
+///
+/// File paths are displayed relative to `root`. When a `baseline` report is
+/// given, the change of the total and of every package is included.
+pub fn markdown(report: &CoverageReport, packages: &[PackageCoverage], root: &Path,
+                baseline: Option<(&CoverageReport, &[PackageCoverage])>) -> String {
+    let mut md = String::new();
+
+    md.push_str("### Coverage report\n\n");
+    md.push_str(&format!("**Total: {:.2}%** ({}/{} lines)", report.percent(), report.covered_lines, report.total_lines));
+    if let Some((baseline, _)) = baseline {
+        md.push_str(&format!(", {} compared to the baseline ({:.2}%)",
+                             change(report.percent(), Some(baseline.percent())), baseline.percent()));
+    }
+    md.push_str("\n\n");
+
+    if !packages.is_empty() {
+        match baseline {
+            Some(_) => md.push_str("| Package | Coverage | Lines | Change |\n|:--|--:|--:|--:|\n"),
+            None => md.push_str("| Package | Coverage | Lines |\n|:--|--:|--:|\n"),
+        }
+        for package in packages {
+            md.push_str(&format!("| {} | {:.2}% | {}/{} |", package.name, package.percent(),
+                                 package.covered_lines, package.total_lines));
+            if let Some((_, baseline_packages)) = baseline {
+                let previous = baseline_packages.iter()
+                    .find(|previous| previous.name == package.name)
+                    .map(PackageCoverage::percent);
+                md.push_str(&format!(" {} |", change(package.percent(), previous)));
+            }
+            md.push('\n');
+        }
+        md.push('\n');
+    }
+
+    let mut files: Vec<_> = report.files.iter().filter(|file| file.total_lines > 0).collect();
+    files.sort_by(|a, b| a.percent().partial_cmp(&b.percent()).unwrap().then(b.total_lines.cmp(&a.total_lines)));
+    if !files.is_empty() {
+        md.push_str("<details><summary>Lowest covered files</summary>\n\n");
+        md.push_str("| File | Coverage | Lines |\n|:--|--:|--:|\n");
+        for file in files.into_iter().take(LOWEST_FILES) {
+            let path = file.path.strip_prefix(root).unwrap_or(&file.path);
+            md.push_str(&format!("| `{}` | {:.2}% | {}/{} |\n", path.display(), file.percent(),
+                                 file.covered_lines, file.total_lines));
+        }
+        md.push_str("\n</details>\n");
+    }
+
+    md
+}
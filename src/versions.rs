@@ -0,0 +1,129 @@
+use semver::Version;
+use serde_json;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Marks the root `index.html` as ours, so that it can be regenerated while a
+/// hand-written one is left alone.
+const GENERATED_MARKER: &str = "<!-- generated by cargo-travis -->";
+
+/// A directory of published documentation, as stored in `versions.json`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VersionEntry {
+    /// Path of the directory on the deploy branch, e.g. the branch name
+    pub path: String,
+    /// Path of the documentation's entry point on the deploy branch
+    pub link: String,
+    /// Version of the crate in the manifest, if there's a single one
+    pub version: Option<String>,
+    pub updated: String,
+    pub commit: String,
+    /// The branch built, which `path` may not be named after
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// The tag built, for the documentation of a release
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+/// Read the published versions, none if the file doesn't exist yet.
+pub fn load(path: &Path) -> io::Result<Vec<VersionEntry>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save(path: &Path, entries: &[VersionEntry]) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(entries).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    fs::write(path, json)
+}
+
+/// Replace the entry with the same path as `entry`, or add it, keeping the
+/// entries sorted by path.
+pub fn update(entries: &mut Vec<VersionEntry>, entry: VersionEntry) {
+    entries.retain(|existing| existing.path != entry.path);
+    entries.push(entry);
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+}
+
+/// The release with the highest version, ignoring the versions which aren't
+/// valid semver.
+pub fn latest_release(entries: &[VersionEntry]) -> Option<&VersionEntry> {
+    entries.iter()
+        .filter(|entry| entry.tag.is_some())
+        .filter_map(|entry| entry.version.as_ref().and_then(|version| Version::parse(version).ok()).map(|version| (version, entry)))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, entry)| entry)
+}
+
+/// Whether the `index.html` at `path` is missing or was generated by one of
+/// the functions below, i.e. whether it can be overwritten.
+pub fn is_generated_index(path: &Path) -> bool {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents.contains(GENERATED_MARKER),
+        Err(_) => !path.exists(),
+    }
+}
+
+/// Render the page listing all the published documentation.
+pub fn index_html(title: &str, entries: &[VersionEntry]) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n");
+    html.push_str(GENERATED_MARKER);
+    html.push_str("\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape(title)));
+    html.push_str("<style>body { font-family: sans-serif; margin: 2em; } td { padding: 0.2em 1em 0.2em 0; } \
+                   .commit { font-family: monospace; }</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+    html.push_str("<table>\n<tr><th>Documentation</th><th>Version</th><th>Updated</th><th>Commit</th></tr>\n");
+    for entry in entries {
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td class=\"commit\">{}</td></tr>\n",
+            escape(&entry.link), escape(&entry.path),
+            escape(entry.version.as_ref().map_or("", |version| &version[..])),
+            escape(entry.updated.get(..10).unwrap_or(&entry.updated)),
+            escape(entry.commit.get(..8).unwrap_or(&entry.commit))));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// Render a page redirecting to `link`, relative to the page.
+pub fn redirect_html(link: &str) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n");
+    html.push_str(GENERATED_MARKER);
+    html.push_str("\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<meta http-equiv=\"refresh\" content=\"0; url={}\">\n", escape(link)));
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<a href=\"{}\">Redirect</a>\n", escape(link)));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Render a page linking to the documentation of every member of a
+/// workspace, given as their name and the link to their documentation.
+pub fn members_html(title: &str, members: &[(String, String)]) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n");
+    html.push_str(GENERATED_MARKER);
+    html.push_str("\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape(title)));
+    html.push_str("<style>body { font-family: sans-serif; margin: 2em; }</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+    html.push_str("<ul>\n");
+    for &(ref name, ref link) in members {
+        html.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", escape(link), escape(name)));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,180 @@
+extern crate cargo_travis;
+extern crate git2;
+
+use cargo_travis::deploy::{Author, DeployBranch, Remote};
+use git2::Repository;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A fresh directory for the test `name`, with a bare repository in
+/// `remote.git` standing for the remote.
+fn setup(name: &str) -> (PathBuf, Remote) {
+    let dir = env::temp_dir().join(format!("cargo-travis-test-{}-{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    Repository::init_bare(dir.join("remote.git")).unwrap();
+    let remote = Remote {
+        url: dir.join("remote.git").to_string_lossy().into_owned(),
+        token: None,
+    };
+    (dir, remote)
+}
+
+fn checkout(remote: &Remote, path: &Path) -> DeployBranch {
+    DeployBranch::checkout(remote, "gh-pages", path, &Author::default(), Some(1)).unwrap()
+}
+
+/// A checkout of the whole history, fetched and pushed with libgit2
+fn full_checkout(remote: &Remote, path: &Path) -> DeployBranch {
+    DeployBranch::checkout(remote, "gh-pages", path, &Author::default(), None).unwrap()
+}
+
+/// The files on the deploy branch of the remote
+fn remote_files(dir: &Path) -> Vec<String> {
+    let repo = Repository::open_bare(dir.join("remote.git")).unwrap();
+    let tree = repo.find_reference("refs/heads/gh-pages").unwrap().peel_to_tree().unwrap();
+    tree.iter().map(|entry| entry.name().unwrap().to_string()).collect()
+}
+
+#[test]
+fn push_new_branch() {
+    let (dir, remote) = setup("new");
+    let deploy = checkout(&remote, &dir.join("checkout"));
+    fs::write(deploy.path().join("a"), "a").unwrap();
+    assert!(deploy.commit("first").unwrap().is_some());
+    assert!(deploy.push(false).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a"]);
+
+    // Nothing changed, nothing to commit
+    assert!(deploy.commit("second").unwrap().is_none());
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn retry_after_rejected_push() {
+    let (dir, remote) = setup("retry");
+    let first = checkout(&remote, &dir.join("first"));
+    fs::write(first.path().join("a"), "a").unwrap();
+    first.commit("first").unwrap();
+    fs::write(first.path().join("a"), "aa").unwrap();
+    first.commit("first again").unwrap();
+    assert!(first.push(false).unwrap());
+
+    // The others only fetch the latest commit, which git has to push
+    let second = checkout(&remote, &dir.join("second"));
+    let third = checkout(&remote, &dir.join("third"));
+    assert!(Repository::open(third.path()).unwrap().is_shallow());
+    fs::write(second.path().join("b"), "b").unwrap();
+    second.commit("second").unwrap();
+    assert!(second.push(false).unwrap());
+
+    // third is behind now, its push is rejected until it fetches again
+    fs::write(third.path().join("c"), "c").unwrap();
+    third.commit("third").unwrap();
+    assert!(!third.push(false).unwrap());
+    third.fetch().unwrap();
+    fs::write(third.path().join("c"), "c").unwrap();
+    third.commit("third").unwrap();
+    assert!(third.push(false).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "c"]);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn truncate_and_force_push() {
+    let (dir, remote) = setup("truncate");
+    let deploy = checkout(&remote, &dir.join("checkout"));
+    for name in &["a", "b", "c"] {
+        fs::write(deploy.path().join(name), name).unwrap();
+        deploy.commit(name).unwrap();
+    }
+    deploy.push(false).unwrap();
+
+    let head = deploy.truncate_history(2).unwrap();
+    assert!(deploy.push(true).unwrap());
+    let repo = Repository::open_bare(dir.join("remote.git")).unwrap();
+    let commit = repo.find_commit(head).unwrap();
+    assert_eq!(commit.message(), Some("c"));
+    assert!(commit.parent(0).unwrap().parents().next().is_none());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "c"]);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn forced_push_keeps_concurrent_updates() {
+    let (dir, remote) = setup("lease");
+    let first = checkout(&remote, &dir.join("first"));
+    let second = checkout(&remote, &dir.join("second"));
+    for name in &["a", "b"] {
+        fs::write(first.path().join(name), name).unwrap();
+        first.commit(name).unwrap();
+    }
+    assert!(first.push(true).unwrap());
+
+    // second fetched before the branch existed, libgit2 checks it's still missing
+    fs::write(second.path().join("c"), "c").unwrap();
+    second.commit("c").unwrap();
+    assert!(!second.push(true).unwrap());
+
+    // third is shallow, git pushes with a lease on what it fetched
+    let third = checkout(&remote, &dir.join("third"));
+    assert!(Repository::open(third.path()).unwrap().is_shallow());
+    fs::write(first.path().join("d"), "d").unwrap();
+    first.commit("d").unwrap();
+    assert!(first.push(true).unwrap());
+    fs::write(third.path().join("e"), "e").unwrap();
+    third.commit("e").unwrap();
+    assert!(!third.push(true).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "d"]);
+
+    third.fetch().unwrap();
+    fs::write(third.path().join("e"), "e").unwrap();
+    third.commit("e").unwrap();
+    assert!(third.push(true).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "d", "e"]);
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn libgit2_fetch_and_push() {
+    let (dir, remote) = setup("libgit2");
+    let first = full_checkout(&remote, &dir.join("first"));
+    fs::write(first.path().join("a"), "a").unwrap();
+    first.commit("first").unwrap();
+    fs::write(first.path().join("a"), "aa").unwrap();
+    first.commit("first again").unwrap();
+    assert!(first.push(false).unwrap());
+
+    let second = full_checkout(&remote, &dir.join("second"));
+    let third = full_checkout(&remote, &dir.join("third"));
+    let repo = Repository::open(second.path()).unwrap();
+    assert!(!repo.is_shallow());
+    assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().parent(0).unwrap().message(), Some("first"));
+    assert_eq!(fs::read_to_string(second.path().join("a")).unwrap(), "aa");
+    fs::write(second.path().join("b"), "b").unwrap();
+    second.commit("second").unwrap();
+    assert!(second.push(false).unwrap());
+
+    fs::write(third.path().join("c"), "c").unwrap();
+    third.commit("third").unwrap();
+    assert!(!third.push(false).unwrap());
+    third.fetch().unwrap();
+    fs::write(third.path().join("c"), "c").unwrap();
+    third.commit("third").unwrap();
+    assert!(third.push(false).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "c"]);
+
+    // second is behind third, its rewritten history isn't forced over it
+    fs::write(second.path().join("d"), "d").unwrap();
+    second.commit("fourth").unwrap();
+    second.truncate_history(1).unwrap();
+    assert!(!second.push(true).unwrap());
+    second.fetch().unwrap();
+    fs::write(second.path().join("d"), "d").unwrap();
+    second.commit("fourth").unwrap();
+    second.truncate_history(1).unwrap();
+    assert!(second.push(true).unwrap());
+    assert_eq!(remote_files(&dir), vec!["a", "b", "c", "d"]);
+    fs::remove_dir_all(&dir).ok();
+}